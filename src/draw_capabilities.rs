@@ -95,6 +95,9 @@ pub struct DrawCapabilities {
     pub scissor_test: Option<Scissor>,
     pub color_draw_mask: (bool, bool, bool, bool),
     pub depth_draw_mask: bool,
+    /// Primitives are discarded before rasterization, for programs that only capture the
+    /// vertex stage outputs with transform feedback
+    pub rasterizer_discard: bool,
 }
 
 impl Default for DrawCapabilities {
@@ -107,6 +110,7 @@ impl Default for DrawCapabilities {
             scissor_test: Default::default(),
             color_draw_mask: (true, true, true, true),
             depth_draw_mask: true,
+            rasterizer_discard: false,
         }
     }
 }
@@ -129,6 +133,7 @@ impl DrawCapabilities {
             scissor_test,
             color_draw_mask: color_draw_mask.unwrap_or((true, true, true, true)),
             depth_draw_mask: depth_draw_mask.unwrap_or(true),
+            rasterizer_discard: false,
         }
     }
 
@@ -151,6 +156,13 @@ impl DrawCapabilities {
         DrawCapabilities::default()
     }
 
+    pub fn discard_rasterizer() -> Self {
+        DrawCapabilities {
+            rasterizer_discard: true,
+            ..Default::default()
+        }
+    }
+
     pub fn get_mut_stencil_state(&mut self) -> Option<&mut StencilTestState> {
         match &mut self.stencil_test {
             Some(stencil_test) => Some(stencil_test),
@@ -235,6 +247,14 @@ impl DrawCapabilities {
         graphics.depth_mask(self.depth_draw_mask);
     }
 
+    pub fn set_rasterizer_discard(&self, graphics: &Graphics) {
+        if self.rasterizer_discard {
+            graphics.enable_capability(WebGlCapability::RASTERIZER_DISCARD);
+        } else {
+            graphics.disable_capability(WebGlCapability::RASTERIZER_DISCARD);
+        }
+    }
+
     pub fn set_capabilities(&self, graphics: &Graphics) {
         self.set_blend_state(graphics);
         self.set_cull_mode(graphics);
@@ -243,5 +263,6 @@ impl DrawCapabilities {
         self.set_scissor_test(graphics);
         self.set_color_mask(graphics);
         self.set_depth_mask(graphics);
+        self.set_rasterizer_discard(graphics);
     }
 }
@@ -5,7 +5,7 @@ use wasm_bindgen::JsValue;
 use web_sys::{WebGl2RenderingContext as gl, WebGlFramebuffer};
 mod constants;
 use crate::{
-    FramebufferMaskBits, GlTexture2D, Graphics, MagFilter, Renderbuffer, TextureBindTarget,
+    FramebufferMaskBits, GlMaterial, GlTexture2D, Graphics, MagFilter, Renderbuffer, TextureBindTarget,
 };
 pub use constants::*;
 
@@ -95,6 +95,16 @@ impl Framebuffer {
         self.context.draw_buffers(&buffers_array)
     }
 
+    /// Sets the draw buffers so every fragment output of `material` writes to the color
+    /// attachment with the same index as its location
+    pub fn set_draw_buffers_for_material(&self, material: &GlMaterial) {
+        let buffers_array = js_sys::Array::new();
+        for buffer in material.draw_buffers() {
+            buffers_array.push(&JsValue::from(buffer));
+        }
+        self.context.draw_buffers(&buffers_array)
+    }
+
     pub fn bind(&self, target: FramebufferBinding) {
         self.unbind();
         self.context
@@ -0,0 +1,47 @@
+use std::cell::Ref;
+
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::Response;
+use webgl2_shader_definition::{FunctionLibrary, MemoryFunctionLibrary};
+
+use crate::Graphics;
+
+impl Graphics {
+    /// Library used to resolve the `ImportFn` definitions of the sources compiled at runtime,
+    /// programs already created are not affected
+    pub fn set_function_library<L: FunctionLibrary + 'static>(&self, library: L) {
+        *self.function_library.borrow_mut() = Box::new(library);
+    }
+
+    pub fn function_library(&self) -> Ref<'_, Box<dyn FunctionLibrary>> {
+        self.function_library.borrow()
+    }
+}
+
+/// Fetches the function file at `url` and inserts it in `library` under `path`, the path used by
+/// the `ImportFn` definitions that import from it. The format comes from the extension of `path`.
+pub async fn fetch_function_file(
+    library: &mut MemoryFunctionLibrary,
+    path: &str,
+    url: &str,
+) -> Result<(), JsValue> {
+    let window = web_sys::window().expect("There is no window");
+    let response: Response = JsFuture::from(window.fetch_with_str(url))
+        .await?
+        .dyn_into()?;
+    if !response.ok() {
+        return Err(JsValue::from(format!(
+            "Could not fetch function file |url: {}| |status: {}|",
+            url,
+            response.status()
+        )));
+    }
+    let file = JsFuture::from(response.text()?)
+        .await?
+        .as_string()
+        .unwrap_or_default();
+    library
+        .insert_str(path, &file)
+        .map_err(|error| JsValue::from(error.to_string()))
+}
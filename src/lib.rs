@@ -23,9 +23,7 @@ pub use webgl2_shader_definition::*;
 pub use webgl2_shader_generation::*;
 pub use webgl_objects::*;
 mod material;
-pub mod shader_functions;
 pub use material::*;
-pub use shader_functions::*;
 mod draw_capabilities;
 pub use draw_capabilities::*;
 mod texture;
@@ -34,6 +32,16 @@ mod vertex_array_object;
 pub use vertex_array_object::*;
 mod framebuffer;
 pub use framebuffer::*;
+mod transform_feedback;
+pub use transform_feedback::*;
+mod reflection;
+pub use reflection::*;
+mod program_cache;
+pub use program_cache::*;
+mod pending_program;
+pub use pending_program::*;
+mod function_library;
+pub use function_library::*;
 mod util;
 pub use util::*;
 
@@ -82,6 +90,8 @@ pub struct Graphics {
     gl_context: Rc<gl>,
     canvas: HtmlCanvasElement,
     texture_units: RefCell<TextureUnits>,
+    program_cache: RefCell<ProgramCache>,
+    function_library: RefCell<Box<dyn FunctionLibrary>>,
 }
 
 impl Graphics {
@@ -90,6 +100,8 @@ impl Graphics {
             canvas,
             gl_context: Rc::new(render_context),
             texture_units: RefCell::new(TextureUnits::new()),
+            program_cache: RefCell::new(ProgramCache::new()),
+            function_library: RefCell::new(Box::new(MemoryFunctionLibrary::new())),
         })
     }
 
@@ -190,10 +202,10 @@ impl From<WebGLDataType> for GlUniform {
             Type::Mat2 => GlUniform::Float(FloatUniform::Mat2(Mat2::IDENTITY)),
             Type::Mat3 => GlUniform::Float(FloatUniform::Mat3(Mat3::IDENTITY)),
             Type::Mat4 => GlUniform::Float(FloatUniform::Mat4(Mat4::IDENTITY)),
-            Type::Bool => todo!("I am not sure if boolean are useful"),
-            Type::BVec2 => todo!("I am not sure if boolean are useful"),
-            Type::BVec3 => todo!("I am not sure if boolean are useful"),
-            Type::BVec4 => todo!("I am not sure if boolean are useful"),
+            Type::Bool => GlUniform::Bool(BoolUniform::Scalar(false)),
+            Type::BVec2 => GlUniform::Bool(BoolUniform::Vec2(BVec2::FALSE)),
+            Type::BVec3 => GlUniform::Bool(BoolUniform::Vec3(BVec3::FALSE)),
+            Type::BVec4 => GlUniform::Bool(BoolUniform::Vec4(BVec4::FALSE)),
             Type::Int => GlUniform::Int(IntUniform::Scalar(0)),
             Type::IVec2 => GlUniform::Int(IntUniform::Vec2(IVec2::ZERO)),
             Type::IVec3 => GlUniform::Int(IntUniform::Vec3(IVec3::ZERO)),
@@ -220,6 +232,130 @@ impl From<WebGLDataType> for GlUniform {
         }
     }
 }
+
+/// Whether a value of type `data_type` can be uploaded to a uniform declared as `kind`, samplers
+/// take the texture unit as an int
+pub(crate) fn data_type_matches(data_type: WebGLDataType, kind: WebGLDataType) -> bool {
+    data_type == kind || (kind.is_opaque() && data_type == WebGLDataType::Int)
+}
+
+impl GlUniform {
+    /// GLSL type of the value, the element type for arrays
+    pub fn data_type(&self) -> WebGLDataType {
+        use WebGLDataType as Type;
+        match self {
+            GlUniform::Float(uniform) => match uniform {
+                FloatUniform::Scalar(_) | FloatUniform::ScalarArray(_) => Type::Float,
+                FloatUniform::Vec2(_) | FloatUniform::Vec2Array(_) => Type::Vec2,
+                FloatUniform::Vec3(_) | FloatUniform::Vec3Array(_) => Type::Vec3,
+                FloatUniform::Vec4(_) | FloatUniform::Vec4Array(_) => Type::Vec4,
+                FloatUniform::Mat2(_) | FloatUniform::Mat2Array(_) => Type::Mat2,
+                FloatUniform::Mat3(_) | FloatUniform::Mat3Array(_) => Type::Mat3,
+                FloatUniform::Mat4(_) | FloatUniform::Mat4Array(_) => Type::Mat4,
+            },
+            GlUniform::Int(uniform) => match uniform {
+                IntUniform::Scalar(_) | IntUniform::ScalarArray(_) => Type::Int,
+                IntUniform::Vec2(_) | IntUniform::Vec2Array(_) => Type::IVec2,
+                IntUniform::Vec3(_) | IntUniform::Vec3Array(_) => Type::IVec3,
+                IntUniform::Vec4(_) | IntUniform::Vec4Array(_) => Type::IVec4,
+            },
+            GlUniform::UInt(uniform) => match uniform {
+                UIntUniform::Scalar(_) | UIntUniform::ScalarArray(_) => Type::Uint,
+                UIntUniform::Vec2(_) | UIntUniform::Vec2Array(_) => Type::UVec2,
+                UIntUniform::Vec3(_) | UIntUniform::Vec3Array(_) => Type::UVec3,
+                UIntUniform::Vec4(_) | UIntUniform::Vec4Array(_) => Type::UVec4,
+            },
+            GlUniform::Bool(uniform) => match uniform {
+                BoolUniform::Scalar(_) | BoolUniform::ScalarArray(_) => Type::Bool,
+                BoolUniform::Vec2(_) | BoolUniform::Vec2Array(_) => Type::BVec2,
+                BoolUniform::Vec3(_) | BoolUniform::Vec3Array(_) => Type::BVec3,
+                BoolUniform::Vec4(_) | BoolUniform::Vec4Array(_) => Type::BVec4,
+            },
+        }
+    }
+
+    /// Number of elements of an array value, `None` for a single value
+    pub fn array_length(&self) -> Option<u32> {
+        let length = match self {
+            GlUniform::Float(uniform) => match uniform {
+                FloatUniform::ScalarArray(values) => values.len(),
+                FloatUniform::Vec2Array(values) => values.len(),
+                FloatUniform::Vec3Array(values) => values.len(),
+                FloatUniform::Vec4Array(values) => values.len(),
+                FloatUniform::Mat2Array(values) => values.len(),
+                FloatUniform::Mat3Array(values) => values.len(),
+                FloatUniform::Mat4Array(values) => values.len(),
+                _ => return None,
+            },
+            GlUniform::Int(uniform) => match uniform {
+                IntUniform::ScalarArray(values) => values.len(),
+                IntUniform::Vec2Array(values) => values.len(),
+                IntUniform::Vec3Array(values) => values.len(),
+                IntUniform::Vec4Array(values) => values.len(),
+                _ => return None,
+            },
+            GlUniform::UInt(uniform) => match uniform {
+                UIntUniform::ScalarArray(values) => values.len(),
+                UIntUniform::Vec2Array(values) => values.len(),
+                UIntUniform::Vec3Array(values) => values.len(),
+                UIntUniform::Vec4Array(values) => values.len(),
+                _ => return None,
+            },
+            GlUniform::Bool(uniform) => match uniform {
+                BoolUniform::ScalarArray(values) => values.len(),
+                BoolUniform::Vec2Array(values) => values.len(),
+                BoolUniform::Vec3Array(values) => values.len(),
+                BoolUniform::Vec4Array(values) => values.len(),
+                _ => return None,
+            },
+        };
+        Some(length as u32)
+    }
+
+    /// Whether the value can be uploaded to a uniform declared as `kind`, samplers take the
+    /// texture unit as an int
+    pub fn matches_data_type(&self, kind: WebGLDataType) -> bool {
+        data_type_matches(self.data_type(), kind)
+    }
+
+    /// Default value of a declared uniform, an array when `array_length` is set
+    pub fn with_array_length(kind: WebGLDataType, array_length: Option<u32>) -> Self {
+        match array_length {
+            Some(length) => GlUniform::array(kind, length),
+            None => GlUniform::from(kind),
+        }
+    }
+
+    /// Default value of a uniform array of `length` elements of type `kind`
+    pub fn array(kind: WebGLDataType, length: u32) -> Self {
+        use WebGLDataType as Type;
+        let length = length as usize;
+        match kind {
+            Type::Void => panic!("Cannot transform void"),
+
+            Type::Float => GlUniform::Float(FloatUniform::ScalarArray(vec![0.0; length])),
+            Type::Vec2 => GlUniform::Float(FloatUniform::Vec2Array(vec![Vec2::ZERO; length])),
+            Type::Vec3 => GlUniform::Float(FloatUniform::Vec3Array(vec![Vec3::ZERO; length])),
+            Type::Vec4 => GlUniform::Float(FloatUniform::Vec4Array(vec![Vec4::ZERO; length])),
+            Type::Mat2 => GlUniform::Float(FloatUniform::Mat2Array(vec![Mat2::IDENTITY; length])),
+            Type::Mat3 => GlUniform::Float(FloatUniform::Mat3Array(vec![Mat3::IDENTITY; length])),
+            Type::Mat4 => GlUniform::Float(FloatUniform::Mat4Array(vec![Mat4::IDENTITY; length])),
+            Type::Bool => GlUniform::Bool(BoolUniform::ScalarArray(vec![false; length])),
+            Type::BVec2 => GlUniform::Bool(BoolUniform::Vec2Array(vec![BVec2::FALSE; length])),
+            Type::BVec3 => GlUniform::Bool(BoolUniform::Vec3Array(vec![BVec3::FALSE; length])),
+            Type::BVec4 => GlUniform::Bool(BoolUniform::Vec4Array(vec![BVec4::FALSE; length])),
+            Type::Uint => GlUniform::UInt(UIntUniform::ScalarArray(vec![0; length])),
+            Type::UVec2 => GlUniform::UInt(UIntUniform::Vec2Array(vec![UVec2::ZERO; length])),
+            Type::UVec3 => GlUniform::UInt(UIntUniform::Vec3Array(vec![UVec3::ZERO; length])),
+            Type::UVec4 => GlUniform::UInt(UIntUniform::Vec4Array(vec![UVec4::ZERO; length])),
+            Type::IVec2 => GlUniform::Int(IntUniform::Vec2Array(vec![IVec2::ZERO; length])),
+            Type::IVec3 => GlUniform::Int(IntUniform::Vec3Array(vec![IVec3::ZERO; length])),
+            Type::IVec4 => GlUniform::Int(IntUniform::Vec4Array(vec![IVec4::ZERO; length])),
+            // Int and sampler arrays
+            _ => GlUniform::Int(IntUniform::ScalarArray(vec![0; length])),
+        }
+    }
+}
 //Shader object creation
 
 //Redefinition of gl function signatures
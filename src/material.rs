@@ -1,19 +1,30 @@
 use crate::{
-    create_program_from_single_shader_source, shader_program::GlProgram, DeviceLimit,
-    DrawCapabilities, GlTexture2D, GlUniform, Graphics, IntoGlUniform, ProgramCreationError,
-    TextureRef, UniformIndex, TextureBindTarget,
+    shader_program::GlProgram, DeviceLimit, DrawCapabilities, GlTexture2D, GlUniform, Graphics,
+    IntoGlUniform, ProgramCreationError, ProgramInUse, ProgramUniforms, SourceUniformError,
+    TextureBindTarget, TextureRef, UniformIndex, UniformTypeMismatch,
 };
 use std::rc::Rc;
 use web_sys::WebGl2RenderingContext as wgl_context;
-use webgl2_shader_definition::ShaderSource;
+use webgl2_shader_definition::{
+    ShaderAttribute, ShaderGenError, ShaderSource, ShaderUniform, ShaderUniformBlock,
+    ShaderVariant,
+};
 
 #[allow(dead_code)]
 pub struct GlMaterial {
     context: Rc<wgl_context>,
-    pub program: GlProgram,
+    /// Programs created from a source are shared with the other materials that use the same
+    /// generated source, see `Graphics::get_or_create_program`
+    pub program: Rc<GlProgram>,
+    /// Uniform values of this material, pushed to the program when it is used
+    pub uniforms: ProgramUniforms,
     pub draw_capabilities: Vec<DrawCapabilities>,
     max_texture_units: u32,
-    sampled_textures: Vec<(Rc<GlTexture2D>, UniformIndex)>,
+    /// Texture slots of the material, slots declared in the source start without a texture
+    sampled_textures: Vec<(Option<Rc<GlTexture2D>>, UniformIndex)>,
+    fragment_outputs: Vec<ShaderAttribute>,
+    /// Variant the program was generated for, reused by `reload_source`
+    variant: ShaderVariant,
 }
 
 impl GlMaterial {
@@ -22,7 +33,16 @@ impl GlMaterial {
         draw_capabilities: Vec<DrawCapabilities>,
         source: &ShaderSource,
     ) -> Result<Self, ProgramCreationError> {
-        let program = match create_program_from_single_shader_source(graphics, source) {
+        Self::with_source_variant(graphics, draw_capabilities, source, &ShaderVariant::new())
+    }
+
+    pub fn with_source_variant(
+        graphics: &Graphics,
+        draw_capabilities: Vec<DrawCapabilities>,
+        source: &ShaderSource,
+        variant: &ShaderVariant,
+    ) -> Result<Self, ProgramCreationError> {
+        let program = match graphics.get_or_create_program(source, variant) {
             Ok(program) => program,
             Err(error) => return Err(error),
         };
@@ -32,25 +52,147 @@ impl GlMaterial {
                 Ok(max_count) => max_count.as_f64().unwrap() as u32,
                 Err(_) => panic!("Cannot get the max textire unit"),
             };
-        Ok(Self {
-            context: context,
+        let mut material = Self {
+            context,
             program,
+            uniforms: ProgramUniforms::new(),
             draw_capabilities,
             sampled_textures: Vec::new(),
             max_texture_units,
-        })
+            fragment_outputs: source.fragment_outputs().into_iter().cloned().collect(),
+            variant: variant.clone(),
+        };
+        let specialized_source = specialize_source(source, variant)?;
+        material
+            .register_source_uniforms(&specialized_source)
+            .map_err(ProgramCreationError::SourceUniforms)?;
+        Ok(material)
     }
-    pub fn insert_uniform<T: IntoGlUniform>(&mut self, uniform: T, name: &str)->UniformIndex{
-        let uniform = uniform.uniform();
-        self.program.insert_uniform(name, uniform).unwrap()
+
+    /// Replaces the program with the one generated from `source`, for shaders edited while the
+    /// application runs. Uniform indices stay valid, they are resolved again by name and keep
+    /// their values and textures. Uniforms added to the source are registered with their default
+    /// value. If the new source does not compile the error is returned and the current program
+    /// is kept. Uniforms that could not be registered are returned as
+    /// `ProgramCreationError::SourceUniforms` after the program was replaced, the other uniforms
+    /// are registered.
+    pub fn reload_source(
+        &mut self,
+        graphics: &Graphics,
+        source: &ShaderSource,
+    ) -> Result<(), ProgramCreationError> {
+        let specialized_source = specialize_source(source, &self.variant)?;
+        let program = graphics.get_or_create_program(source, &self.variant)?;
+        self.program = program;
+        self.uniforms.rebind(&self.program);
+        self.fragment_outputs = source.fragment_outputs().into_iter().cloned().collect();
+        self.register_source_uniforms(&specialized_source)
+            .map_err(ProgramCreationError::SourceUniforms)
+    }
+
+    /// Registers the uniforms declared in the source with their default value, samplers become
+    /// texture slots and uniform blocks are bound to their binding number. Declarations removed
+    /// by the compiler are skipped, the ones that fail are returned after registering the rest.
+    fn register_source_uniforms(
+        &mut self,
+        source: &ShaderSource,
+    ) -> Result<(), Vec<SourceUniformError>> {
+        let mut collections = vec![
+            &source.common_uniforms,
+            &source.vertex_shader.uniform_collection,
+        ];
+        if let Some(fragment_shader) = &source.fragment_shader {
+            collections.push(&fragment_shader.uniform_collection);
+        }
+        let mut uniforms: Vec<&ShaderUniform> = Vec::new();
+        let mut uniform_blocks: Vec<&ShaderUniformBlock> = Vec::new();
+        for collection in collections {
+            for uniform in &collection.uniforms {
+                if !uniforms.iter().any(|u| u.name == uniform.name) {
+                    uniforms.push(uniform);
+                }
+            }
+            for block in &collection.uniform_blocks {
+                if !uniform_blocks.iter().any(|b| b.name == block.name) {
+                    uniform_blocks.push(block);
+                }
+            }
+        }
+
+        let mut errors = Vec::new();
+        for uniform in uniforms {
+            // Struct uniforms have no single value, their members are set one by one
+            let kind = match uniform.kind.data_type() {
+                Some(kind) => kind,
+                None => continue,
+            };
+            if self.program.reflection.uniform(&uniform.name).is_none() {
+                continue;
+            }
+            // Uniforms kept by `reload_source` keep their value
+            let uniform_index = match self.uniforms.get_uniform_index(&uniform.name) {
+                Some(uniform_index) if self.uniforms.is_active(uniform_index) => uniform_index,
+                _ => {
+                    let value = GlUniform::with_array_length(kind, uniform.array_length);
+                    let checked = self
+                        .program
+                        .check_uniform_type(&uniform.name, value.data_type());
+                    if let Err(error) = checked {
+                        errors.push(SourceUniformError::TypeMismatch(error));
+                        continue;
+                    }
+                    self.uniforms
+                        .insert_unchecked(&self.program, &uniform.name, value)
+                }
+            };
+            let has_slot = self
+                .sampled_textures
+                .iter()
+                .any(|(_, index)| *index == uniform_index);
+            if kind.is_opaque() && !has_slot {
+                self.sampled_textures.push((None, uniform_index));
+            }
+        }
+
+        for block in uniform_blocks {
+            if self.program.reflection.uniform_block(&block.name).is_some() {
+                let bound = self
+                    .program
+                    .set_uniform_block_binding_str(&block.name, block.binding_number);
+                if bound.is_err() {
+                    errors.push(SourceUniformError::BlockBinding {
+                        name: block.name.clone(),
+                        binding_number: block.binding_number,
+                    });
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+    /// Inserts a uniform of the material, the type of `T` is checked against the active uniform.
+    /// A uniform removed by the compiler is kept without a location, see
+    /// `ProgramUniforms::insert_value`
+    pub fn insert_uniform<T: IntoGlUniform>(
+        &mut self,
+        uniform: T,
+        name: &str,
+    ) -> Result<UniformIndex, UniformTypeMismatch> {
+        self.uniforms.insert_value(&self.program, name, uniform)
     }
 
     /// There should be a parameter descriptor and parameter upload functions accompaniying this
     pub fn new(
         graphics: &Graphics,
-        program: GlProgram,
+        mut program: GlProgram,
         draw_capabilities: Vec<DrawCapabilities>,
     ) -> Self {
+        // Uniforms already inserted in the program become the uniforms of the material
+        let uniforms = std::mem::replace(&mut program.uniforms, ProgramUniforms::new());
         let context = graphics.gl_context.clone();
         let max_texture_units =
             match context.get_parameter(DeviceLimit::MAX_VERTEX_TEXTURE_IMAGE_UNITS.value()) {
@@ -58,33 +200,63 @@ impl GlMaterial {
                 Err(_) => panic!("Cannot get the max textire unit"),
             };
         Self {
-            context: context,
-            program,
+            context,
+            program: Rc::new(program),
+            uniforms,
             draw_capabilities,
             sampled_textures: Vec::new(),
             max_texture_units,
+            fragment_outputs: vec![ShaderAttribute::get_default_frag_attribute()],
+            variant: ShaderVariant::new(),
         }
     }
 
+    /// Fragment outputs of the program sorted by location, a material created with `new`
+    /// assumes the default `frag_color` output at location 0
+    pub fn fragment_outputs(&self) -> &[ShaderAttribute] {
+        &self.fragment_outputs
+    }
+
+    /// The buffers for `drawBuffers` that route every fragment output to the color attachment
+    /// with the same index, locations without an output are set to `NONE`
+    pub fn draw_buffers(&self) -> Vec<u32> {
+        draw_buffers_for_outputs(&self.fragment_outputs)
+    }
+
     pub fn set_capabilities(&self, graphics: &Graphics, index: usize) {
         self.draw_capabilities[index].set_capabilities(graphics)
     }
 
-    pub fn push_texture_samplers(&mut self, graphics: &Graphics) {
+    /// Binds the textures of the material to texture units and sets their sampler uniforms.
+    /// Every texture is bound, the first sampler uniform that is no longer an int is returned.
+    pub fn push_texture_samplers(
+        &mut self,
+        graphics: &Graphics,
+    ) -> Result<(), UniformTypeMismatch> {
         let mut texture_refs = Vec::new();
-        for (texture, _) in self.sampled_textures.iter() {
-            texture_refs.push(TextureRef::Texture2D(Rc::clone(texture)))
+        let mut uniform_indices = Vec::new();
+        for (texture, uniform_index) in self.sampled_textures.iter() {
+            // Slots of samplers removed by `reload_source` keep their texture without a unit
+            if !self.uniforms.is_active(*uniform_index) {
+                continue;
+            }
+            if let Some(texture) = texture {
+                texture_refs.push(TextureRef::Texture2D(Rc::clone(texture)));
+                uniform_indices.push(*uniform_index);
+            }
         }
         let bind_data = graphics.bind_textures_to_units(texture_refs);
+        let mut result = Ok(());
         for data in bind_data {
-            let (_, uniform_index) = self.sampled_textures[data.texture_index];
-            self.set_uniform(uniform_index, (data.texture_unit as i32).uniform());
+            let uniform_index = uniform_indices[data.texture_index];
+            result = result.and(self.set_uniform_value(uniform_index, data.texture_unit as i32));
         }
         self.context.active_texture(wgl_context::TEXTURE16);
         self.context.bind_texture(TextureBindTarget::TEXTURE_2D.into(), None);
         self.context.bind_texture(TextureBindTarget::TEXTURE_3D.into(), None);
         self.context.bind_texture(TextureBindTarget::TEXTURE_2D_ARRAY.into(), None);
         self.context.bind_texture(TextureBindTarget::TEXTURE_CUBE_MAP.into(), None);
+        result
     }
 
     /// Gets the uniform location with the passed uniform name
@@ -95,12 +267,17 @@ impl GlMaterial {
         uniform_name: &str,
         texture_ref: Rc<GlTexture2D>,
     ) -> Result<(), String> {
-        if self.sampled_textures.len() as u32 >= self.max_texture_units {
+        let texture_count = self
+            .sampled_textures
+            .iter()
+            .filter(|(texture, _)| texture.is_some())
+            .count();
+        if texture_count as u32 >= self.max_texture_units {
             return Err(format!("Trying to push more texture units than what is supported by this hardware. Current supported count {}", self.max_texture_units));
         }
-        let texture_uniform_index = self
-            .program
-            .insert_uniform(uniform_name, (0 as i32).uniform())?;
+        let texture_uniform_index =
+            self.uniforms
+                .insert_uniform(&self.program, uniform_name, 0i32.uniform())?;
 
         let sampled_texture = self
             .sampled_textures
@@ -108,44 +285,127 @@ impl GlMaterial {
             .position(|(_, uni_index)| *uni_index == texture_uniform_index);
         match sampled_texture{
             Some(index) => {
-                self.sampled_textures[index] = (texture_ref, texture_uniform_index);
+                self.sampled_textures[index] = (Some(texture_ref), texture_uniform_index);
             },
             None => {
-                self.sampled_textures.push((texture_ref, texture_uniform_index));
+                self.sampled_textures.push((Some(texture_ref), texture_uniform_index));
             },
         }
         Ok(())
     }
 
     /// Swaps a texture reference for another texture using the name of the uniform
-    /// It expects that the all the textures in the sampled_textures vector have a corresponding entry
-    /// in the GlProgram uniform list, it is going to panic if this is not the case
+    /// The uniform has to be a sampler already registered in the sampled_textures vector, either by
+    /// `set_texture_sampler_uniform` or by the material source
     pub fn swap_texture_sampler_uniform(
         &mut self,
         uniform_name: &str,
         texture_ref: Rc<GlTexture2D>,
-    ) -> Result<(), ()> {
-        match self.program.uniforms.get_uniform_index(uniform_name) {
-            Some(uniform_index) => {
-                //Get the index of the sampled texture with the same uniform index
-                let texture_index = self
-                    .sampled_textures
-                    .iter()
-                    .position(|(_, uni_index)| *uni_index == uniform_index)
-                    .unwrap();
-                self.sampled_textures[texture_index] = (texture_ref, uniform_index);
-                return Ok(());
+    ) -> Result<(), String> {
+        let uniform_index = self
+            .uniforms
+            .get_uniform_index(uniform_name)
+            .ok_or_else(|| format!("Uniform - {} does not exist", uniform_name))?;
+        //Get the index of the sampled texture with the same uniform index
+        let texture_index = self
+            .sampled_textures
+            .iter()
+            .position(|(_, uni_index)| *uni_index == uniform_index)
+            .ok_or_else(|| format!("Uniform - {} is not a texture sampler", uniform_name))?;
+        self.sampled_textures[texture_index] = (Some(texture_ref), uniform_index);
+        Ok(())
+    }
+
+    /// Strict mode of the uniforms of this material, see `GlProgram::set_strict_uniforms`. The
+    /// program of a material created from a source is shared, its own flag does not apply here.
+    pub fn set_strict_uniforms(&mut self, strict: bool) {
+        self.uniforms.set_strict(strict);
+    }
+
+    pub fn set_uniform(
+        &mut self,
+        uniform_index: UniformIndex,
+        uniform_value: GlUniform,
+    ) -> Result<(), UniformTypeMismatch> {
+        self.uniforms.set_uniform(uniform_index, uniform_value)
+    }
+    pub fn set_uniform_value<T: IntoGlUniform>(
+        &mut self,
+        uniform_index: UniformIndex,
+        uniform: T,
+    ) -> Result<(), UniformTypeMismatch> {
+        self.uniforms.set_uniform_value(uniform_index, uniform)
+    }
+
+    /// Uses the program with the uniform values of this material
+    pub fn use_program(&mut self) -> ProgramInUse<'_> {
+        self.program.use_program_with(&mut self.uniforms)
+    }
+}
+
+// Keyword values of `variant` applied to `source`, the same specialization the program is
+// generated from
+fn specialize_source(
+    source: &ShaderSource,
+    variant: &ShaderVariant,
+) -> Result<ShaderSource, ProgramCreationError> {
+    source
+        .resolve_variant(variant)
+        .and_then(|variant| source.specialize(&variant))
+        .map_err(|error| ProgramCreationError::SourceParsing(ShaderGenError::InvalidVariant(error)))
+}
+
+/// The buffers for `drawBuffers` that route each fragment output to the color attachment with the
+/// same index as its location, up to the highest location. Locations without an output are
+/// `NONE`.
+pub fn draw_buffers_for_outputs(outputs: &[ShaderAttribute]) -> Vec<u32> {
+    let buffer_count = outputs
+        .iter()
+        .map(|output| output.layout_loc + 1)
+        .max()
+        .unwrap_or(0);
+    (0..buffer_count)
+        .map(|location| {
+            if outputs.iter().any(|output| output.layout_loc == location) {
+                wgl_context::COLOR_ATTACHMENT0 + location
+            } else {
+                wgl_context::NONE
             }
-            None => todo!(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WebGLDataType;
+
+    fn output(layout_loc: u32) -> ShaderAttribute {
+        ShaderAttribute {
+            layout_loc,
+            kind: WebGLDataType::Vec4,
+            name: format!("out_{}", layout_loc),
         }
     }
 
-    pub fn set_uniform(&mut self, uniform_index: UniformIndex, uniform_value: GlUniform) {
-        self.program
-            .uniforms
-            .set_uniform(uniform_index, uniform_value);
-    }
-    pub fn set_uniform_value<T: IntoGlUniform>(&mut self, uniform_index: UniformIndex, uniform: T){
-        self.set_uniform(uniform_index, uniform.uniform());
+    #[test]
+    fn draw_buffers_follow_output_locations() {
+        assert_eq!(draw_buffers_for_outputs(&[]), Vec::<u32>::new());
+        assert_eq!(
+            draw_buffers_for_outputs(&[output(0)]),
+            [wgl_context::COLOR_ATTACHMENT0]
+        );
+        assert_eq!(
+            draw_buffers_for_outputs(&[output(2), output(0)]),
+            [
+                wgl_context::COLOR_ATTACHMENT0,
+                wgl_context::NONE,
+                wgl_context::COLOR_ATTACHMENT2,
+            ]
+        );
+        assert_eq!(
+            draw_buffers_for_outputs(&[output(1)]),
+            [wgl_context::NONE, wgl_context::COLOR_ATTACHMENT1]
+        );
     }
 }
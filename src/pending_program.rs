@@ -0,0 +1,172 @@
+use std::rc::Rc;
+
+use js_sys::Promise;
+use wasm_bindgen::JsValue;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{WebGl2RenderingContext as gl, WebGlProgram, WebGlShader};
+use webgl2_shader_definition::{
+    generate_shader_variant_with_library, GeneratedShader, ShaderSource, ShaderStageKind,
+    ShaderVariant, TransformFeedback,
+};
+
+use crate::{
+    program_link_error, shader_diagnostics, GlProgram, Graphics, ProgramCreationError, ShaderType,
+};
+
+/// `COMPLETION_STATUS_KHR` from the `KHR_parallel_shader_compile` extension
+pub const COMPLETION_STATUS_KHR: u32 = 0x91B1;
+
+/// A program whose shaders were sent to the driver without waiting for the compile and link
+/// status. With `KHR_parallel_shader_compile` the driver compiles in the background and
+/// `is_complete` can be polled, without it the status is only checked when the program is
+/// finished, after the caller had the chance to do other work.
+pub struct PendingProgram {
+    context: Rc<gl>,
+    program: Option<WebGlProgram>,
+    vertex_shader: WebGlShader,
+    fragment_shader: WebGlShader,
+    vertex_source: GeneratedShader,
+    fragment_source: GeneratedShader,
+    parallel_compile: bool,
+}
+
+impl Drop for PendingProgram {
+    fn drop(&mut self) {
+        if let Some(program) = &self.program {
+            self.context.delete_program(Some(program));
+        }
+        self.context.delete_shader(Some(&self.vertex_shader));
+        self.context.delete_shader(Some(&self.fragment_shader));
+    }
+}
+
+impl Graphics {
+    /// Whether the `KHR_parallel_shader_compile` extension is available, it is enabled by the call
+    pub fn supports_parallel_shader_compile(&self) -> bool {
+        matches!(
+            self.gl_context.get_extension("KHR_parallel_shader_compile"),
+            Ok(Some(_))
+        )
+    }
+
+    /// Starts compiling and linking the program without blocking on its status, use
+    /// `GeneratedShader::from_code` for shaders that were not generated from a `ShaderSource`
+    pub fn create_pending_program(
+        &self,
+        vertex_source: GeneratedShader,
+        fragment_source: GeneratedShader,
+        transform_feedback: Option<&TransformFeedback>,
+    ) -> Result<PendingProgram, ProgramCreationError> {
+        let vertex_shader =
+            self.compile_shader_from_str(&vertex_source.code, ShaderType::VERTEX_SHADER);
+        let fragment_shader =
+            self.compile_shader_from_str(&fragment_source.code, ShaderType::FRAGMENT_SHADER);
+        let program =
+            match self.link_gl_program(&vertex_shader, &fragment_shader, transform_feedback) {
+                Ok(program) => program,
+                Err(error) => {
+                    self.gl_context.delete_shader(Some(&vertex_shader));
+                    self.gl_context.delete_shader(Some(&fragment_shader));
+                    return Err(ProgramCreationError::Program(error));
+                }
+            };
+        Ok(PendingProgram {
+            context: self.gl_context.clone(),
+            program: Some(program),
+            vertex_shader,
+            fragment_shader,
+            vertex_source,
+            fragment_source,
+            parallel_compile: self.supports_parallel_shader_compile(),
+        })
+    }
+}
+
+/// Same as `create_program_from_shader_variant` but the program is returned before the driver
+/// finished compiling it, only the shader generation errors are returned right away
+pub fn create_pending_program_from_shader_variant(
+    graphics: &Graphics,
+    source: &ShaderSource,
+    variant: &ShaderVariant,
+) -> Result<PendingProgram, ProgramCreationError> {
+    let generated =
+        generate_shader_variant_with_library(source, variant, &**graphics.function_library());
+    match generated {
+        Ok((vs_shader, fs_shader)) => graphics.create_pending_program(
+            vs_shader,
+            fs_shader,
+            source.transform_feedback.as_ref(),
+        ),
+        Err(error) => Err(ProgramCreationError::SourceParsing(error)),
+    }
+}
+
+impl PendingProgram {
+    /// True once checking the status will not block, always true without
+    /// `KHR_parallel_shader_compile`
+    pub fn is_complete(&self) -> bool {
+        match &self.program {
+            Some(program) if self.parallel_compile => self
+                .context
+                .get_program_parameter(program, COMPLETION_STATUS_KHR)
+                .as_bool()
+                .unwrap_or(true),
+            _ => true,
+        }
+    }
+
+    /// Checks the compile and link status, blocking if the program is not complete yet
+    pub fn finish(mut self) -> Result<GlProgram, ProgramCreationError> {
+        let program = self.program.take().unwrap();
+        match program_link_error(&self.context, &program) {
+            None => {
+                self.context.detach_shader(&program, &self.vertex_shader);
+                self.context.detach_shader(&program, &self.fragment_shader);
+                Ok(GlProgram::from_linked_program(&self.context, program))
+            }
+            // The link fails when a shader does not compile, report the shader errors instead
+            Some(link_error) => {
+                let mut diagnostics = shader_diagnostics(
+                    &self.context,
+                    &self.vertex_shader,
+                    ShaderStageKind::Vertex,
+                    &self.vertex_source,
+                );
+                diagnostics.extend(shader_diagnostics(
+                    &self.context,
+                    &self.fragment_shader,
+                    ShaderStageKind::Fragment,
+                    &self.fragment_source,
+                ));
+                if diagnostics.is_empty() {
+                    Err(ProgramCreationError::Program(link_error))
+                } else {
+                    Err(ProgramCreationError::ShaderCompilation(diagnostics))
+                }
+            }
+        }
+    }
+
+    /// Waits for the program without blocking the main thread, the status is checked once per
+    /// event loop turn
+    pub async fn wait(self) -> Result<GlProgram, ProgramCreationError> {
+        // Without the extension the status is checked after yielding once, so the caller can
+        // start other work while the driver compiles
+        if !self.parallel_compile {
+            next_tick().await;
+        }
+        while !self.is_complete() {
+            next_tick().await;
+        }
+        self.finish()
+    }
+}
+
+// Resolves on the next turn of the event loop
+async fn next_tick() {
+    let promise = Promise::new(&mut |resolve, _reject| {
+        let window = web_sys::window().expect("There is no window");
+        let _ = window.set_timeout_with_callback_and_timeout_and_arguments_0(&resolve, 0);
+    });
+    let _: Result<JsValue, JsValue> = JsFuture::from(promise).await;
+}
@@ -0,0 +1,164 @@
+use std::{
+    collections::HashMap,
+    rc::{Rc, Weak},
+};
+
+use webgl2_shader_definition::{
+    generate_shader_variant_with_library, ShaderSource, ShaderVariant, TransformFeedback,
+    TransformFeedbackMode,
+};
+
+use crate::{create_program_from_mapped_source, GlProgram, Graphics, ProgramCreationError};
+
+/// Counters of the program cache, for profiling
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProgramCacheStats {
+    /// Programs compiled and linked through the cache
+    pub compiles: u32,
+    pub hits: u32,
+    pub misses: u32,
+}
+
+/// Everything a linked program depends on. The generated shader strings already contain the
+/// defines of the variant, so two variants that generate the same code share a program.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ProgramKey {
+    pub vertex_shader: String,
+    pub fragment_shader: String,
+    pub transform_feedback: Option<(TransformFeedbackMode, Vec<String>)>,
+}
+
+impl ProgramKey {
+    pub fn new(
+        vertex_shader: &str,
+        fragment_shader: &str,
+        transform_feedback: Option<&TransformFeedback>,
+    ) -> Self {
+        Self {
+            vertex_shader: vertex_shader.to_string(),
+            fragment_shader: fragment_shader.to_string(),
+            transform_feedback: transform_feedback.map(|transform_feedback| {
+                (transform_feedback.mode, transform_feedback.varyings.clone())
+            }),
+        }
+    }
+}
+
+/// Programs created from a `ShaderSource`, keyed by the generated shader strings. The cache only
+/// keeps weak references, a program is deleted once no material uses it.
+#[derive(Default)]
+pub struct ProgramCache {
+    programs: HashMap<ProgramKey, Weak<GlProgram>>,
+    stats: ProgramCacheStats,
+}
+
+impl ProgramCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the program stored with `key` if it is still alive, counting a hit or a miss
+    pub fn get(&mut self, key: &ProgramKey) -> Option<Rc<GlProgram>> {
+        match self.programs.get(key).and_then(|program| program.upgrade()) {
+            Some(program) => {
+                self.stats.hits += 1;
+                Some(program)
+            }
+            None => {
+                self.stats.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Stores a newly compiled program, entries of programs that were dropped are removed
+    pub fn insert(&mut self, key: ProgramKey, program: &Rc<GlProgram>) {
+        self.programs
+            .retain(|_, program| program.strong_count() > 0);
+        self.programs.insert(key, Rc::downgrade(program));
+        self.stats.compiles += 1;
+    }
+
+    /// Number of cached programs that are still alive
+    pub fn len(&self) -> usize {
+        self.programs
+            .values()
+            .filter(|program| program.strong_count() > 0)
+            .count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn stats(&self) -> ProgramCacheStats {
+        self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = ProgramCacheStats::default();
+    }
+
+    pub fn clear(&mut self) {
+        self.programs.clear();
+    }
+}
+
+impl Graphics {
+    /// Returns the program for the variant of the source, reusing a program with the same
+    /// generated source if one is alive instead of compiling it again
+    pub fn get_or_create_program(
+        &self,
+        source: &ShaderSource,
+        variant: &ShaderVariant,
+    ) -> Result<Rc<GlProgram>, ProgramCreationError> {
+        let generated =
+            generate_shader_variant_with_library(source, variant, &**self.function_library());
+        let (vs_shader, fs_shader) = match generated {
+            Ok(shaders) => shaders,
+            Err(error) => return Err(ProgramCreationError::SourceParsing(error)),
+        };
+        let transform_feedback = source.transform_feedback.as_ref();
+        let key = ProgramKey::new(&vs_shader.code, &fs_shader.code, transform_feedback);
+        if let Some(program) = self.program_cache.borrow_mut().get(&key) {
+            return Ok(program);
+        }
+        let program = Rc::new(create_program_from_mapped_source(
+            self,
+            &vs_shader,
+            &fs_shader,
+            transform_feedback,
+        )?);
+        self.program_cache.borrow_mut().insert(key, &program);
+        Ok(program)
+    }
+
+    pub fn program_cache_stats(&self) -> ProgramCacheStats {
+        self.program_cache.borrow().stats()
+    }
+
+    pub fn clear_program_cache(&self) {
+        self.program_cache.borrow_mut().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_compare_the_generated_sources() {
+        let transform_feedback = TransformFeedback {
+            mode: TransformFeedbackMode::Separate,
+            varyings: vec!["position".into(), "velocity".into()],
+        };
+        let key = ProgramKey::new("vs", "fs", Some(&transform_feedback));
+        assert_eq!(key, ProgramKey::new("vs", "fs", Some(&transform_feedback)));
+        assert_ne!(key, ProgramKey::new("vs", "fs", None));
+        assert_ne!(key, ProgramKey::new("vs", "fs2", Some(&transform_feedback)));
+
+        let mut interleaved = transform_feedback.clone();
+        interleaved.mode = TransformFeedbackMode::Interleaved;
+        assert_ne!(key, ProgramKey::new("vs", "fs", Some(&interleaved)));
+    }
+}
@@ -0,0 +1,502 @@
+use std::fmt;
+
+use wasm_bindgen::JsValue;
+use web_sys::{WebGl2RenderingContext as gl, WebGlProgram};
+use webgl2_shader_definition::{
+    DeclarationKind, ShaderSource, ShaderStruct, ShaderUniform, ShaderUniformBlock, UniformKind,
+    WebGLDataType,
+};
+
+use crate::ProgramParamerter;
+
+/// Maps a GL type enum (from `WebGlActiveInfo::type_`) to the matching `WebGLDataType`
+pub fn data_type_from_gl(gl_type: u32) -> Option<WebGLDataType> {
+    use WebGLDataType as Type;
+    let data_type = match gl_type {
+        gl::BOOL => Type::Bool,
+        gl::INT => Type::Int,
+        gl::UNSIGNED_INT => Type::Uint,
+        gl::FLOAT => Type::Float,
+        gl::FLOAT_VEC2 => Type::Vec2,
+        gl::FLOAT_VEC3 => Type::Vec3,
+        gl::FLOAT_VEC4 => Type::Vec4,
+        gl::BOOL_VEC2 => Type::BVec2,
+        gl::BOOL_VEC3 => Type::BVec3,
+        gl::BOOL_VEC4 => Type::BVec4,
+        gl::INT_VEC2 => Type::IVec2,
+        gl::INT_VEC3 => Type::IVec3,
+        gl::INT_VEC4 => Type::IVec4,
+        gl::UNSIGNED_INT_VEC2 => Type::UVec2,
+        gl::UNSIGNED_INT_VEC3 => Type::UVec3,
+        gl::UNSIGNED_INT_VEC4 => Type::UVec4,
+        gl::FLOAT_MAT2 => Type::Mat2,
+        gl::FLOAT_MAT3 => Type::Mat3,
+        gl::FLOAT_MAT4 => Type::Mat4,
+        gl::SAMPLER_2D => Type::Sampler2D,
+        gl::SAMPLER_3D => Type::Sampler3D,
+        gl::SAMPLER_CUBE => Type::SamplerCube,
+        gl::SAMPLER_CUBE_SHADOW => Type::SamplerCubeShadow,
+        gl::SAMPLER_2D_SHADOW => Type::Sampler2DShadow,
+        gl::SAMPLER_2D_ARRAY => Type::Sampler2DArray,
+        gl::SAMPLER_2D_ARRAY_SHADOW => Type::Sampler2DArrayShadow,
+        gl::INT_SAMPLER_2D => Type::ISampler2D,
+        gl::INT_SAMPLER_3D => Type::ISampler3D,
+        gl::INT_SAMPLER_CUBE => Type::ISamplerCube,
+        gl::INT_SAMPLER_2D_ARRAY => Type::ISampler2DArray,
+        gl::UNSIGNED_INT_SAMPLER_2D => Type::USampler2D,
+        gl::UNSIGNED_INT_SAMPLER_3D => Type::USampler3D,
+        gl::UNSIGNED_INT_SAMPLER_CUBE => Type::USamplerCube,
+        gl::UNSIGNED_INT_SAMPLER_2D_ARRAY => Type::USampler2DArray,
+        _ => return None,
+    };
+    Some(data_type)
+}
+
+/// Uniform of the default block that survived linking. Array names are stored without the `[0]`
+/// suffix WebGL adds to them.
+#[derive(Debug, Clone)]
+pub struct ActiveUniform {
+    pub name: String,
+    pub gl_type: u32,
+    pub kind: Option<WebGLDataType>,
+    /// Number of array elements, 1 if it is not an array
+    pub size: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct ActiveBlockMember {
+    pub name: String,
+    pub gl_type: u32,
+    pub kind: Option<WebGLDataType>,
+    pub size: u32,
+    /// Offsets and strides in bytes, as the driver laid out the block
+    pub offset: u32,
+    pub array_stride: u32,
+    pub matrix_stride: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct ActiveUniformBlock {
+    pub name: String,
+    pub index: u32,
+    pub binding: u32,
+    pub data_size: u32,
+    pub members: Vec<ActiveBlockMember>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ActiveAttribute {
+    pub name: String,
+    pub gl_type: u32,
+    pub kind: Option<WebGLDataType>,
+    pub size: u32,
+    pub location: i32,
+}
+
+/// A difference between what a `ShaderSource` declares and what the linked program uses
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReflectionMismatch {
+    /// Declared in the source but removed by the compiler because it is never used
+    Inactive { name: String, kind: DeclarationKind },
+    /// Used by the program but not declared in the source, usually declared in `local_import`
+    Undeclared { name: String, kind: DeclarationKind },
+    TypeMismatch {
+        name: String,
+        declared: WebGLDataType,
+        active: WebGLDataType,
+    },
+    LocationMismatch {
+        name: String,
+        declared: u32,
+        active: i32,
+    },
+    /// The driver placed a block member at a different offset than the std140 layout
+    BlockMemberOffset {
+        block: String,
+        member: String,
+        declared: u32,
+        active: u32,
+    },
+    /// The driver needs a bigger buffer than the std140 layout computed for the block
+    BlockSize {
+        block: String,
+        declared: u32,
+        active: u32,
+    },
+}
+
+impl fmt::Display for ReflectionMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReflectionMismatch::Inactive { name, kind } => write!(
+                f,
+                "{} '{}' is declared but not active in the program",
+                kind.as_str(),
+                name
+            ),
+            ReflectionMismatch::Undeclared { name, kind } => write!(
+                f,
+                "{} '{}' is active in the program but not declared in the source",
+                kind.as_str(),
+                name
+            ),
+            ReflectionMismatch::TypeMismatch {
+                name,
+                declared,
+                active,
+            } => write!(
+                f,
+                "'{}' is declared as {} but the program uses it as {}",
+                name,
+                declared.as_str(),
+                active.as_str()
+            ),
+            ReflectionMismatch::LocationMismatch {
+                name,
+                declared,
+                active,
+            } => write!(
+                f,
+                "attribute '{}' is declared at location {} but the program uses location {}",
+                name, declared, active
+            ),
+            ReflectionMismatch::BlockMemberOffset {
+                block,
+                member,
+                declared,
+                active,
+            } => write!(
+                f,
+                "member '{}' of block '{}' is at offset {} but the std140 layout expects {}",
+                member, block, active, declared
+            ),
+            ReflectionMismatch::BlockSize {
+                block,
+                declared,
+                active,
+            } => write!(
+                f,
+                "block '{}' needs {} bytes but the std140 layout only has {}",
+                block, active, declared
+            ),
+        }
+    }
+}
+
+/// Active uniforms, uniform blocks and attributes of a linked program
+#[derive(Debug, Clone, Default)]
+pub struct ProgramReflection {
+    pub uniforms: Vec<ActiveUniform>,
+    pub uniform_blocks: Vec<ActiveUniformBlock>,
+    pub attributes: Vec<ActiveAttribute>,
+}
+
+fn base_name(name: String) -> String {
+    match name.strip_suffix("[0]") {
+        Some(name) => name.to_string(),
+        None => name,
+    }
+}
+
+fn program_parameter(context: &gl, program: &WebGlProgram, parameter: ProgramParamerter) -> u32 {
+    context
+        .get_program_parameter(program, parameter.into())
+        .as_f64()
+        .unwrap_or(0.0) as u32
+}
+
+fn active_uniforms_parameter(
+    context: &gl,
+    program: &WebGlProgram,
+    indices: &js_sys::Array,
+    pname: u32,
+) -> Vec<i32> {
+    let values = context.get_active_uniforms(program, indices, pname);
+    js_sys::Array::from(&values)
+        .iter()
+        .map(|value| value.as_f64().unwrap_or(-1.0) as i32)
+        .collect()
+}
+
+impl ProgramReflection {
+    /// Queries the active uniforms, uniform blocks and attributes of a linked program
+    pub fn from_program(context: &gl, program: &WebGlProgram) -> Self {
+        let mut reflection = Self::default();
+
+        let uniform_count = program_parameter(context, program, ProgramParamerter::ACTIVE_UNIFORMS);
+        let indices = js_sys::Array::new();
+        for index in 0..uniform_count {
+            indices.push(&JsValue::from(index));
+        }
+        let block_indices =
+            active_uniforms_parameter(context, program, &indices, gl::UNIFORM_BLOCK_INDEX);
+        let offsets = active_uniforms_parameter(context, program, &indices, gl::UNIFORM_OFFSET);
+        let array_strides =
+            active_uniforms_parameter(context, program, &indices, gl::UNIFORM_ARRAY_STRIDE);
+        let matrix_strides =
+            active_uniforms_parameter(context, program, &indices, gl::UNIFORM_MATRIX_STRIDE);
+
+        let block_count =
+            program_parameter(context, program, ProgramParamerter::ACTIVE_UNIFORM_BLOCKS);
+        for index in 0..block_count {
+            let block_parameter = |pname: u32| {
+                context
+                    .get_active_uniform_block_parameter(program, index, pname)
+                    .ok()
+                    .and_then(|value| value.as_f64())
+                    .unwrap_or(0.0) as u32
+            };
+            reflection.uniform_blocks.push(ActiveUniformBlock {
+                name: context
+                    .get_active_uniform_block_name(program, index)
+                    .unwrap_or_default(),
+                index,
+                binding: block_parameter(gl::UNIFORM_BLOCK_BINDING),
+                data_size: block_parameter(gl::UNIFORM_BLOCK_DATA_SIZE),
+                members: Vec::new(),
+            });
+        }
+
+        for index in 0..uniform_count {
+            let info = match context.get_active_uniform(program, index) {
+                Some(info) => info,
+                None => continue,
+            };
+            let i = index as usize;
+            let name = base_name(info.name());
+            let block_index = block_indices.get(i).copied().unwrap_or(-1);
+            if block_index < 0 {
+                reflection.uniforms.push(ActiveUniform {
+                    name,
+                    gl_type: info.type_(),
+                    kind: data_type_from_gl(info.type_()),
+                    size: info.size() as u32,
+                });
+            } else if let Some(block) = reflection
+                .uniform_blocks
+                .iter_mut()
+                .find(|block| block.index == block_index as u32)
+            {
+                block.members.push(ActiveBlockMember {
+                    name,
+                    gl_type: info.type_(),
+                    kind: data_type_from_gl(info.type_()),
+                    size: info.size() as u32,
+                    offset: offsets.get(i).copied().unwrap_or(0).max(0) as u32,
+                    array_stride: array_strides.get(i).copied().unwrap_or(0).max(0) as u32,
+                    matrix_stride: matrix_strides.get(i).copied().unwrap_or(0).max(0) as u32,
+                });
+            }
+        }
+
+        let attribute_count =
+            program_parameter(context, program, ProgramParamerter::ACTIVE_ATTRIBUTES);
+        for index in 0..attribute_count {
+            if let Some(info) = context.get_active_attrib(program, index) {
+                let name = base_name(info.name());
+                reflection.attributes.push(ActiveAttribute {
+                    location: context.get_attrib_location(program, &name),
+                    name,
+                    gl_type: info.type_(),
+                    kind: data_type_from_gl(info.type_()),
+                    size: info.size() as u32,
+                });
+            }
+        }
+
+        reflection
+    }
+
+    pub fn uniform(&self, name: &str) -> Option<&ActiveUniform> {
+        self.uniforms.iter().find(|uniform| uniform.name == name)
+    }
+
+    pub fn uniform_block(&self, name: &str) -> Option<&ActiveUniformBlock> {
+        self.uniform_blocks.iter().find(|block| block.name == name)
+    }
+
+    pub fn attribute(&self, name: &str) -> Option<&ActiveAttribute> {
+        self.attributes.iter().find(|attribute| attribute.name == name)
+    }
+
+    /// Compares the program with the declarations of `source`. For programs created from a
+    /// variant, pass the source returned by `ShaderSource::specialize` for that variant.
+    pub fn diff(&self, source: &ShaderSource) -> Vec<ReflectionMismatch> {
+        let mut mismatches = Vec::new();
+
+        let mut collections = vec![&source.common_uniforms, &source.vertex_shader.uniform_collection];
+        if let Some(fragment_shader) = &source.fragment_shader {
+            collections.push(&fragment_shader.uniform_collection);
+        }
+        let mut declared_uniforms: Vec<&ShaderUniform> = Vec::new();
+        let mut declared_blocks: Vec<&ShaderUniformBlock> = Vec::new();
+        for collection in collections {
+            for uniform in &collection.uniforms {
+                if !declared_uniforms.iter().any(|u| u.name == uniform.name) {
+                    declared_uniforms.push(uniform);
+                }
+            }
+            for block in &collection.uniform_blocks {
+                if !declared_blocks.iter().any(|b| b.name == block.name) {
+                    declared_blocks.push(block);
+                }
+            }
+        }
+
+        let mut declared_names = Vec::new();
+        for uniform in declared_uniforms {
+            let mut leaves = Vec::new();
+            flatten_uniform(uniform, "", &source.structs, &mut Vec::new(), &mut leaves);
+            let mut any_active = false;
+            for (name, declared) in &leaves {
+                if let Some(active) = self.uniform(name) {
+                    any_active = true;
+                    if let Some(active_kind) = active.kind {
+                        if active_kind != *declared {
+                            mismatches.push(ReflectionMismatch::TypeMismatch {
+                                name: name.clone(),
+                                declared: *declared,
+                                active: active_kind,
+                            });
+                        }
+                    }
+                }
+            }
+            if !any_active {
+                mismatches.push(ReflectionMismatch::Inactive {
+                    name: uniform.name.clone(),
+                    kind: DeclarationKind::Uniform,
+                });
+            }
+            declared_names.extend(leaves.into_iter().map(|(name, _)| name));
+        }
+        for active in &self.uniforms {
+            if !declared_names.contains(&active.name) {
+                mismatches.push(ReflectionMismatch::Undeclared {
+                    name: active.name.clone(),
+                    kind: DeclarationKind::Uniform,
+                });
+            }
+        }
+
+        for block in &declared_blocks {
+            let active_block = match self.uniform_block(&block.name) {
+                Some(active_block) => active_block,
+                None => {
+                    mismatches.push(ReflectionMismatch::Inactive {
+                        name: block.name.clone(),
+                        kind: DeclarationKind::UniformBlock,
+                    });
+                    continue;
+                }
+            };
+            // Blocks with invalid declarations are reported by `ShaderSource::validate`
+            let layout = match block.std140_layout(&source.structs) {
+                Ok(layout) => layout,
+                Err(_) => continue,
+            };
+            if active_block.data_size > layout.size {
+                mismatches.push(ReflectionMismatch::BlockSize {
+                    block: block.name.clone(),
+                    declared: layout.size,
+                    active: active_block.data_size,
+                });
+            }
+            for member in &active_block.members {
+                if let Some(declared) = layout.member(&member.name) {
+                    if declared.offset != member.offset {
+                        mismatches.push(ReflectionMismatch::BlockMemberOffset {
+                            block: block.name.clone(),
+                            member: member.name.clone(),
+                            declared: declared.offset,
+                            active: member.offset,
+                        });
+                    }
+                }
+            }
+        }
+        for active_block in &self.uniform_blocks {
+            if !declared_blocks.iter().any(|block| block.name == active_block.name) {
+                mismatches.push(ReflectionMismatch::Undeclared {
+                    name: active_block.name.clone(),
+                    kind: DeclarationKind::UniformBlock,
+                });
+            }
+        }
+
+        let declared_attributes = &source.vertex_shader.attributes;
+        for attribute in declared_attributes {
+            match self.attribute(&attribute.name) {
+                Some(active) => {
+                    if let Some(active_kind) = active.kind {
+                        if active_kind != attribute.kind {
+                            mismatches.push(ReflectionMismatch::TypeMismatch {
+                                name: attribute.name.clone(),
+                                declared: attribute.kind,
+                                active: active_kind,
+                            });
+                        }
+                    }
+                    if active.location >= 0 && active.location as u32 != attribute.layout_loc {
+                        mismatches.push(ReflectionMismatch::LocationMismatch {
+                            name: attribute.name.clone(),
+                            declared: attribute.layout_loc,
+                            active: active.location,
+                        });
+                    }
+                }
+                None => mismatches.push(ReflectionMismatch::Inactive {
+                    name: attribute.name.clone(),
+                    kind: DeclarationKind::Attribute,
+                }),
+            }
+        }
+        for active in &self.attributes {
+            let is_declared = declared_attributes
+                .iter()
+                .any(|attribute| attribute.name == active.name);
+            if !is_declared && !active.name.starts_with("gl_") {
+                mismatches.push(ReflectionMismatch::Undeclared {
+                    name: active.name.clone(),
+                    kind: DeclarationKind::Attribute,
+                });
+            }
+        }
+
+        mismatches
+    }
+}
+
+// Expands struct uniforms into the names WebGL reports for them (`lights[1].color`)
+fn flatten_uniform<'a>(
+    uniform: &'a ShaderUniform,
+    prefix: &str,
+    structs: &'a [ShaderStruct],
+    struct_stack: &mut Vec<&'a str>,
+    leaves: &mut Vec<(String, WebGLDataType)>,
+) {
+    let name = format!("{}{}", prefix, uniform.name);
+    match &uniform.kind {
+        UniformKind::Data(kind) => leaves.push((name, *kind)),
+        UniformKind::Struct(struct_name) => {
+            let shader_struct = match structs.iter().find(|s| &s.name == struct_name) {
+                Some(shader_struct) => shader_struct,
+                None => return,
+            };
+            if struct_stack.contains(&struct_name.as_str()) {
+                return;
+            }
+            struct_stack.push(struct_name);
+            let element_prefixes = match uniform.array_length {
+                Some(len) => (0..len).map(|i| format!("{}[{}].", name, i)).collect(),
+                None => vec![format!("{}.", name)],
+            };
+            for element_prefix in element_prefixes {
+                for member in &shader_struct.members {
+                    flatten_uniform(member, &element_prefix, structs, struct_stack, leaves);
+                }
+            }
+            struct_stack.pop();
+        }
+    }
+}
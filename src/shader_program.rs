@@ -1,13 +1,22 @@
 use glam::*;
-use std::{collections::HashMap, rc::Rc};
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    fmt,
+    rc::Rc,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 use wasm_bindgen::JsValue;
 use web_sys::{WebGlProgram, WebGlShader, WebGlUniformLocation};
 use webgl2_shader_definition::{
-    generate_shader_str_from_single_source, ShaderSource, ShaderUniform,
+    generate_shader_variant_with_library, parse_info_log, GeneratedShader, ShaderDiagnostic,
+    ShaderGenError, ShaderSource, ShaderStageKind, ShaderUniform, ShaderVariant, TransformFeedback,
+    TransformFeedbackMode, WebGLDataType,
 };
 
 use crate::{
-    GlShader, GlUniform, Graphics, IndexType, PrimitiveType, ProgramParamerter, ShaderType,
+    data_type_matches, GlShader, GlTransformFeedback, GlUniform, Graphics, IndexType,
+    IntoGlUniform, PrimitiveType, ProgramParamerter, ProgramReflection, ShaderType,
 };
 use web_sys::WebGl2RenderingContext as wgl_context;
 
@@ -23,30 +32,60 @@ impl Graphics {
         shader: &str,
         shader_type: ShaderType,
     ) -> Result<WebGlShader, JsValue> {
+        let gl_shader = self.compile_shader_from_str(shader, shader_type);
+        match shader_compile_error(&self.gl_context, &gl_shader, shader) {
+            Some(error) => Err(error),
+            None => Ok(gl_shader),
+        }
+    }
+
+    /// Starts compiling the shader without waiting for the result
+    pub(crate) fn compile_shader_from_str(
+        &self,
+        shader: &str,
+        shader_type: ShaderType,
+    ) -> WebGlShader {
         let gl_shader = self.gl_context.create_shader(shader_type.into()).unwrap();
         self.gl_context.shader_source(&gl_shader, shader);
         self.gl_context.compile_shader(&gl_shader);
-        let compilation_status = self
-            .gl_context
-            .get_shader_parameter(&gl_shader, wgl_context::COMPILE_STATUS);
-        if !compilation_status
-            .as_bool()
-            .expect("Compilation status has to be bool")
-        {
-            //self.gl_context.draw_elements_with_f64(mode, count, type_, offset)
-            match self.gl_context.get_shader_info_log(&gl_shader) {
-                Some(shader_log) => Err(JsValue::from(shader_log + "/n " + shader)),
-                None => Err(JsValue::from("Shader compilation failed")),
+        gl_shader
+    }
+
+    pub fn create_gl_program(
+        &self,
+        vertex_shader: &WebGlShader,
+        fragment_shader: &WebGlShader,
+    ) -> Result<WebGlProgram, JsValue> {
+        self.create_gl_program_with_feedback(vertex_shader, fragment_shader, None)
+    }
+
+    /// Same as `create_gl_program`, the `transform_feedback` varyings are set before linking
+    pub fn create_gl_program_with_feedback(
+        &self,
+        vertex_shader: &WebGlShader,
+        fragment_shader: &WebGlShader,
+        transform_feedback: Option<&TransformFeedback>,
+    ) -> Result<WebGlProgram, JsValue> {
+        let shader_program =
+            self.link_gl_program(vertex_shader, fragment_shader, transform_feedback)?;
+        match program_link_error(&self.gl_context, &shader_program) {
+            Some(error) => Err(error),
+            None => {
+                self.gl_context
+                    .detach_shader(&shader_program, vertex_shader);
+                self.gl_context
+                    .detach_shader(&shader_program, fragment_shader);
+                Ok(shader_program)
             }
-        } else {
-            Ok(gl_shader)
         }
     }
 
-    pub fn create_gl_program(
+    /// Starts linking the program without waiting for the result
+    pub(crate) fn link_gl_program(
         &self,
         vertex_shader: &WebGlShader,
         fragment_shader: &WebGlShader,
+        transform_feedback: Option<&TransformFeedback>,
     ) -> Result<WebGlProgram, JsValue> {
         match self.gl_context.create_program() {
             Some(shader_program) => {
@@ -54,34 +93,96 @@ impl Graphics {
                     .attach_shader(&shader_program, vertex_shader);
                 self.gl_context
                     .attach_shader(&shader_program, fragment_shader);
-                self.gl_context.link_program(&shader_program);
-
-                let link_param = self
-                    .gl_context
-                    .get_program_parameter(&shader_program, ProgramParamerter::LINK_STATUS.into());
-
-                if link_param.as_bool().unwrap() {
-                    self.gl_context
-                        .detach_shader(&shader_program, vertex_shader);
-                    self.gl_context
-                        .detach_shader(&shader_program, fragment_shader);
-                    Ok(shader_program)
-                } else {
-                    match self.gl_context.get_program_info_log(&shader_program) {
-                        Some(info_log) => Err(JsValue::from(info_log)),
-                        None => Err(JsValue::from("Program link status false")),
+                if let Some(transform_feedback) = transform_feedback {
+                    let varyings = js_sys::Array::new();
+                    for varying in &transform_feedback.varyings {
+                        varyings.push(&JsValue::from(varying.as_str()));
                     }
+                    let buffer_mode = match transform_feedback.mode {
+                        TransformFeedbackMode::Interleaved => wgl_context::INTERLEAVED_ATTRIBS,
+                        TransformFeedbackMode::Separate => wgl_context::SEPARATE_ATTRIBS,
+                    };
+                    self.gl_context.transform_feedback_varyings(
+                        &shader_program,
+                        &varyings,
+                        buffer_mode,
+                    );
                 }
+                self.gl_context.link_program(&shader_program);
+                Ok(shader_program)
             }
             None => Err(JsValue::from("Could not create webgl program")),
         }
     }
 }
 
+/// Waits for the shader compilation and returns the info log followed by the source if it failed
+pub(crate) fn shader_compile_error(
+    context: &wgl_context,
+    gl_shader: &WebGlShader,
+    shader: &str,
+) -> Option<JsValue> {
+    shader_info_log(context, gl_shader).map(|shader_log| JsValue::from(shader_log + "\n" + shader))
+}
+
+/// Waits for the shader compilation and returns the info log if it failed
+pub(crate) fn shader_info_log(context: &wgl_context, gl_shader: &WebGlShader) -> Option<String> {
+    let compilation_status = context.get_shader_parameter(gl_shader, wgl_context::COMPILE_STATUS);
+    if !compilation_status
+        .as_bool()
+        .expect("Compilation status has to be bool")
+    {
+        match context.get_shader_info_log(gl_shader) {
+            Some(shader_log) if !shader_log.trim().is_empty() => Some(shader_log),
+            _ => Some(String::from("Shader compilation failed")),
+        }
+    } else {
+        None
+    }
+}
+
+/// Waits for the shader compilation and parses the info log if it failed, the messages are
+/// located in the source with the line map of `shader`
+pub(crate) fn shader_diagnostics(
+    context: &wgl_context,
+    gl_shader: &WebGlShader,
+    stage: ShaderStageKind,
+    shader: &GeneratedShader,
+) -> Vec<ShaderDiagnostic> {
+    match shader_info_log(context, gl_shader) {
+        Some(shader_log) => parse_info_log(&shader_log, stage, shader),
+        None => Vec::new(),
+    }
+}
+
+/// Waits for the link and returns the info log if it failed, the program is deleted
+pub(crate) fn program_link_error(
+    context: &wgl_context,
+    shader_program: &WebGlProgram,
+) -> Option<JsValue> {
+    let link_param =
+        context.get_program_parameter(shader_program, ProgramParamerter::LINK_STATUS.into());
+
+    if link_param.as_bool().unwrap() {
+        None
+    } else {
+        let error = match context.get_program_info_log(shader_program) {
+            Some(info_log) => JsValue::from(info_log),
+            None => JsValue::from("Program link status false"),
+        };
+        context.delete_program(Some(shader_program));
+        Some(error)
+    }
+}
+
 pub struct GlProgram {
     context: Rc<wgl_context>,
     pub program: WebGlProgram,
     pub uniforms: ProgramUniforms,
+    /// Active uniforms, uniform blocks and attributes, read right after linking
+    pub reflection: ProgramReflection,
+    /// Id of the `ProgramUniforms` whose values are currently set in the program
+    uniforms_in_program: Cell<usize>,
 }
 
 impl GlProgram {
@@ -90,15 +191,33 @@ impl GlProgram {
         vertex_shader: &GlShader,
         fragment_shader: &GlShader,
     ) -> Result<Self, JsValue> {
-        match graphics.create_gl_program(&vertex_shader.shader, &fragment_shader.shader) {
-            Ok(program) => Ok(Self {
-                context: graphics.gl_context.clone(),
-                program,
-                uniforms: ProgramUniforms::new(),
-            }),
+        Self::with_transform_feedback(graphics, vertex_shader, fragment_shader, None)
+    }
+    pub fn with_transform_feedback(
+        graphics: &Graphics,
+        vertex_shader: &GlShader,
+        fragment_shader: &GlShader,
+        transform_feedback: Option<&TransformFeedback>,
+    ) -> Result<Self, JsValue> {
+        match graphics.create_gl_program_with_feedback(
+            &vertex_shader.shader,
+            &fragment_shader.shader,
+            transform_feedback,
+        ) {
+            Ok(program) => Ok(Self::from_linked_program(&graphics.gl_context, program)),
             Err(error) => Err(error),
         }
     }
+    /// Wraps a program that was linked successfully
+    pub(crate) fn from_linked_program(context: &Rc<wgl_context>, program: WebGlProgram) -> Self {
+        Self {
+            context: Rc::clone(context),
+            reflection: ProgramReflection::from_program(context, &program),
+            program,
+            uniforms: ProgramUniforms::new(),
+            uniforms_in_program: Cell::new(0),
+        }
+    }
     pub fn get_uniform_block_index(&self, uniform_block_name: &str) -> Result<u32, &str> {
         let index = self
             .context
@@ -131,89 +250,266 @@ impl GlProgram {
             .get_uniform_location(&self.program, uniform_name)
     }
 
+    /// In strict mode setting a uniform with a value of the wrong type panics in debug builds
+    /// instead of only returning `UniformTypeMismatch`. Only the uniforms stored in the program
+    /// are affected, `GlMaterial::new` keeps the flag but materials created from a source have
+    /// their own uniforms, see `GlMaterial::set_strict_uniforms`.
+    pub fn set_strict_uniforms(&mut self, strict: bool) {
+        self.uniforms.set_strict(strict);
+    }
+
     pub fn use_program(&mut self) -> ProgramInUse {
-        self.context.use_program(Some(&self.program));
-        ProgramInUse::new(self)
+        let Self {
+            context,
+            program,
+            uniforms,
+            uniforms_in_program,
+            ..
+        } = self;
+        ProgramInUse::new(context, program, uniforms_in_program, uniforms)
     }
-    fn unuse_program(&self) {
-        self.context.use_program(None);
+
+    /// Same as `use_program` but the uniform values come from `uniforms` instead of the ones
+    /// stored in the program, used by materials that share a program
+    pub fn use_program_with<'a>(&'a self, uniforms: &'a mut ProgramUniforms) -> ProgramInUse<'a> {
+        ProgramInUse::new(
+            &self.context,
+            &self.program,
+            &self.uniforms_in_program,
+            uniforms,
+        )
     }
 
     /// Use to cache the uniform's location and set a default value to it
     /// When in the render process use the function PushUniform / PushAllUniforms inside the ProgramInUse struct
     /// to make sure the program state is updated with the values here
+    /// The value has to match the type of the active uniform
     pub fn insert_uniform(
         &mut self,
         uniform_name: &str,
         uniform: GlUniform,
     ) -> Result<UniformIndex, String> {
-        match self.get_uniform_location(uniform_name) {
-            Some(location) => {
-                let uniform_data = UniformData {
-                    value: uniform,
-                    location,
-                    in_program: false,
-                };
-                Ok(self.uniforms.insert(uniform_name, uniform_data))
+        let uniform_data = self.uniform_data(uniform_name, uniform)?;
+        Ok(self.uniforms.insert(uniform_name, uniform_data))
+    }
+
+    /// Checks a value of type `data_type` against the type of the active uniform, uniforms that
+    /// are not active always pass
+    pub(crate) fn check_uniform_type(
+        &self,
+        uniform_name: &str,
+        data_type: WebGLDataType,
+    ) -> Result<(), UniformTypeMismatch> {
+        match self
+            .reflection
+            .uniform(uniform_name)
+            .and_then(|active| active.kind)
+        {
+            Some(active_kind) if !data_type_matches(data_type, active_kind) => {
+                Err(UniformTypeMismatch {
+                    name: uniform_name.to_string(),
+                    expected: active_kind,
+                    got: data_type,
+                    expected_array_length: None,
+                    got_array_length: None,
+                })
             }
+            _ => Ok(()),
+        }
+    }
+
+    fn uniform_data(&self, uniform_name: &str, uniform: GlUniform) -> Result<UniformData, String> {
+        self.check_uniform_type(uniform_name, uniform.data_type())
+            .map_err(|error| error.to_string())?;
+        match self.get_uniform_location(uniform_name) {
+            Some(location) => Ok(UniformData {
+                value: uniform,
+                location: Some(location),
+                in_program: false,
+            }),
             None => Err(format!("Uniform - {} does not exist", uniform_name)),
         }
     }
 
-    pub fn insert_shader_uniforms(&mut self, uniforms: &Vec<ShaderUniform>) -> Result<(), ()> {
+    /// Inserts the declared uniforms with their default value. Struct uniforms and uniforms
+    /// removed by the compiler are skipped, the ones whose type does not match the active uniform
+    /// are returned after inserting the rest.
+    pub fn insert_shader_uniforms(
+        &mut self,
+        uniforms: &[ShaderUniform],
+    ) -> Result<(), Vec<SourceUniformError>> {
+        let mut errors = Vec::new();
         for uniform in uniforms {
-            match self.insert_uniform(&uniform.name, GlUniform::from(uniform.kind)) {
-                Ok(_) => {}
-                Err(_) => return Err(()),
+            // Struct uniforms are set member by member, they have no single GlUniform value
+            let kind = match uniform.kind.data_type() {
+                Some(kind) => kind,
+                None => continue,
+            };
+            let location = match self.get_uniform_location(&uniform.name) {
+                Some(location) => location,
+                None => continue,
+            };
+            let value = GlUniform::with_array_length(kind, uniform.array_length);
+            if let Err(error) = self.check_uniform_type(&uniform.name, value.data_type()) {
+                errors.push(SourceUniformError::TypeMismatch(error));
+                continue;
             }
+            let uniform_data = UniformData {
+                value,
+                location: Some(location),
+                in_program: false,
+            };
+            self.uniforms.insert(&uniform.name, uniform_data);
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
         }
-        Ok(())
     }
 }
 
 #[derive(Debug)]
 pub enum ProgramCreationError {
-    SourceParsing,
-    VertexShader(JsValue),
-    FragmentShader(JsValue),
-    ShaderGeneration { vertex: JsValue, fragment: JsValue },
+    SourceParsing(ShaderGenError),
+    /// Messages of the info logs of the shaders that did not compile
+    ShaderCompilation(Vec<ShaderDiagnostic>),
     Program(JsValue),
+    /// Uniforms and uniform blocks of the source a material could not set up on the program
+    SourceUniforms(Vec<SourceUniformError>),
 }
-pub fn create_program_from_single_shader_source(
-    graphics: &Graphics,
-    source: &ShaderSource,
-) -> Result<GlProgram, ProgramCreationError> {
-    match generate_shader_str_from_single_source(source) {
-        Ok((vs_shader, fs_shader)) => {
-            /*web_sys::console::log_1(&JsValue::from(format!(
-                "Vertex shader: {:?}
-            Fragment shader: {:?}",
-                vs_shader, fs_shader
-            )));*/
-            match (
-                GlShader::new(graphics, &vs_shader, ShaderType::VERTEX_SHADER),
-                GlShader::new(graphics, &fs_shader, ShaderType::FRAGMENT_SHADER),
-            ) {
-                (Err(vertex_error), Err(fragment_error)) => {
-                    Err(ProgramCreationError::ShaderGeneration {
-                        vertex: vertex_error,
-                        fragment: fragment_error,
-                    })
+
+/// A uniform or uniform block declared in a `ShaderSource` that a material could not register
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SourceUniformError {
+    /// The declared type does not match the type of the active uniform
+    TypeMismatch(UniformTypeMismatch),
+    /// The block is active but its binding number could not be set
+    BlockBinding { name: String, binding_number: u32 },
+}
+
+impl fmt::Display for SourceUniformError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SourceUniformError::TypeMismatch(error) => write!(f, "{}", error),
+            SourceUniformError::BlockBinding {
+                name,
+                binding_number,
+            } => write!(
+                f,
+                "uniform block '{}' could not be bound to binding {}",
+                name, binding_number
+            ),
+        }
+    }
+}
+
+impl fmt::Display for ProgramCreationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProgramCreationError::SourceParsing(error) => write!(f, "{}", error),
+            ProgramCreationError::ShaderCompilation(diagnostics) => {
+                for (i, diagnostic) in diagnostics.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{}", diagnostic)?;
                 }
-                (Ok(_), Err(frag_error)) => Err(ProgramCreationError::FragmentShader(frag_error)),
-                (Err(vert_error), Ok(_)) => Err(ProgramCreationError::VertexShader(vert_error)),
-                (Ok(vert_shader), Ok(frag_shader)) => {
-                    match GlProgram::new(graphics, &vert_shader, &frag_shader) {
-                        Ok(program) => Ok(program),
-                        Err(error) => Err(ProgramCreationError::Program(error)),
+                Ok(())
+            }
+            ProgramCreationError::Program(error) => write!(f, "Program link failed: {:?}", error),
+            ProgramCreationError::SourceUniforms(errors) => {
+                for (i, error) in errors.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(f)?;
                     }
+                    write!(f, "{}", error)?;
                 }
+                Ok(())
             }
         }
-        Err(_) => Err(ProgramCreationError::SourceParsing),
     }
 }
 
+pub fn create_program_from_single_shader_source(
+    graphics: &Graphics,
+    source: &ShaderSource,
+) -> Result<GlProgram, ProgramCreationError> {
+    create_program_from_shader_variant(graphics, source, &ShaderVariant::new())
+}
+
+/// Creates the program for one variant of the source, keywords not set in `variant` use their default
+pub fn create_program_from_shader_variant(
+    graphics: &Graphics,
+    source: &ShaderSource,
+    variant: &ShaderVariant,
+) -> Result<GlProgram, ProgramCreationError> {
+    let generated =
+        generate_shader_variant_with_library(source, variant, &**graphics.function_library());
+    match generated {
+        Ok((vs_shader, fs_shader)) => create_program_from_mapped_source(
+            graphics,
+            &vs_shader,
+            &fs_shader,
+            source.transform_feedback.as_ref(),
+        ),
+        Err(error) => Err(ProgramCreationError::SourceParsing(error)),
+    }
+}
+
+/// Compiles and links already generated vertex and fragment shader strings
+pub fn create_program_from_generated_source(
+    graphics: &Graphics,
+    vs_shader: &str,
+    fs_shader: &str,
+    transform_feedback: Option<&TransformFeedback>,
+) -> Result<GlProgram, ProgramCreationError> {
+    create_program_from_mapped_source(
+        graphics,
+        &GeneratedShader::from_code(vs_shader.to_string()),
+        &GeneratedShader::from_code(fs_shader.to_string()),
+        transform_feedback,
+    )
+}
+
+/// Compiles and links generated shaders, compile errors are located in the `ShaderSource` with
+/// the line map of each stage
+pub fn create_program_from_mapped_source(
+    graphics: &Graphics,
+    vs_shader: &GeneratedShader,
+    fs_shader: &GeneratedShader,
+    transform_feedback: Option<&TransformFeedback>,
+) -> Result<GlProgram, ProgramCreationError> {
+    let context = &graphics.gl_context;
+    let vertex_shader =
+        graphics.compile_shader_from_str(&vs_shader.code, ShaderType::VERTEX_SHADER);
+    let fragment_shader =
+        graphics.compile_shader_from_str(&fs_shader.code, ShaderType::FRAGMENT_SHADER);
+    let mut diagnostics =
+        shader_diagnostics(context, &vertex_shader, ShaderStageKind::Vertex, vs_shader);
+    diagnostics.extend(shader_diagnostics(
+        context,
+        &fragment_shader,
+        ShaderStageKind::Fragment,
+        fs_shader,
+    ));
+    let program = if diagnostics.is_empty() {
+        match graphics.create_gl_program_with_feedback(
+            &vertex_shader,
+            &fragment_shader,
+            transform_feedback,
+        ) {
+            Ok(program) => Ok(GlProgram::from_linked_program(context, program)),
+            Err(error) => Err(ProgramCreationError::Program(error)),
+        }
+    } else {
+        Err(ProgramCreationError::ShaderCompilation(diagnostics))
+    };
+    context.delete_shader(Some(&vertex_shader));
+    context.delete_shader(Some(&fragment_shader));
+    program
+}
+
 pub struct UniformSetter {
     context: Rc<wgl_context>,
 }
@@ -286,20 +582,106 @@ impl UniformSetter {
             &value.to_cols_array(),
         );
     }
+    //float 32 arrays
+    pub fn set_uniform_f32_array(&self, location: &WebGlUniformLocation, values: &[f32]) {
+        self.context
+            .uniform1fv_with_f32_array(Some(location), values);
+    }
+    pub fn set_uniform_vec2_array(&self, location: &WebGlUniformLocation, values: &[Vec2]) {
+        self.context
+            .uniform2fv_with_f32_array(Some(location), bytemuck::cast_slice(values));
+    }
+    pub fn set_uniform_vec3_array(&self, location: &WebGlUniformLocation, values: &[Vec3]) {
+        self.context
+            .uniform3fv_with_f32_array(Some(location), bytemuck::cast_slice(values));
+    }
+    pub fn set_uniform_vec4_array(&self, location: &WebGlUniformLocation, values: &[Vec4]) {
+        self.context
+            .uniform4fv_with_f32_array(Some(location), bytemuck::cast_slice(values));
+    }
+    //int 32 arrays
+    pub fn set_uniform_i32_array(&self, location: &WebGlUniformLocation, values: &[i32]) {
+        self.context
+            .uniform1iv_with_i32_array(Some(location), values);
+    }
+    pub fn set_uniform_ivec2_array(&self, location: &WebGlUniformLocation, values: &[IVec2]) {
+        self.context
+            .uniform2iv_with_i32_array(Some(location), bytemuck::cast_slice(values));
+    }
+    pub fn set_uniform_ivec3_array(&self, location: &WebGlUniformLocation, values: &[IVec3]) {
+        self.context
+            .uniform3iv_with_i32_array(Some(location), bytemuck::cast_slice(values));
+    }
+    pub fn set_uniform_ivec4_array(&self, location: &WebGlUniformLocation, values: &[IVec4]) {
+        self.context
+            .uniform4iv_with_i32_array(Some(location), bytemuck::cast_slice(values));
+    }
+    //unsigned int 32 arrays
+    pub fn set_uniform_u32_array(&self, location: &WebGlUniformLocation, values: &[u32]) {
+        self.context
+            .uniform1uiv_with_u32_array(Some(location), values);
+    }
+    pub fn set_uniform_uvec2_array(&self, location: &WebGlUniformLocation, values: &[UVec2]) {
+        self.context
+            .uniform2uiv_with_u32_array(Some(location), bytemuck::cast_slice(values));
+    }
+    pub fn set_uniform_uvec3_array(&self, location: &WebGlUniformLocation, values: &[UVec3]) {
+        self.context
+            .uniform3uiv_with_u32_array(Some(location), bytemuck::cast_slice(values));
+    }
+    pub fn set_uniform_uvec4_array(&self, location: &WebGlUniformLocation, values: &[UVec4]) {
+        self.context
+            .uniform4uiv_with_u32_array(Some(location), bytemuck::cast_slice(values));
+    }
+    //float 32 matrix arrays
+    pub fn set_uniform_mat2_array(&self, location: &WebGlUniformLocation, values: &[Mat2]) {
+        self.context.uniform_matrix2fv_with_f32_array(
+            Some(location),
+            false,
+            bytemuck::cast_slice(values),
+        );
+    }
+    pub fn set_uniform_mat3_array(&self, location: &WebGlUniformLocation, values: &[Mat3]) {
+        self.context.uniform_matrix3fv_with_f32_array(
+            Some(location),
+            false,
+            bytemuck::cast_slice(values),
+        );
+    }
+    pub fn set_uniform_mat4_array(&self, location: &WebGlUniformLocation, values: &[Mat4]) {
+        self.context.uniform_matrix4fv_with_f32_array(
+            Some(location),
+            false,
+            bytemuck::cast_slice(values),
+        );
+    }
 }
 
 pub struct ProgramInUse<'a> {
-    program: &'a mut GlProgram,
+    context: Rc<wgl_context>,
+    uniforms: &'a mut ProgramUniforms,
     uniform_setter: UniformSetter,
 }
 
 impl<'a> ProgramInUse<'a> {
-    fn new(program: &'a mut GlProgram) -> Self {
+    fn new(
+        context: &Rc<wgl_context>,
+        program: &WebGlProgram,
+        uniforms_in_program: &Cell<usize>,
+        uniforms: &'a mut ProgramUniforms,
+    ) -> Self {
+        context.use_program(Some(program));
+        // Another set of uniforms was pushed since the last time these were used
+        if uniforms_in_program.get() != uniforms.id {
+            uniforms.set_all_dirty();
+            uniforms_in_program.set(uniforms.id);
+        }
         let uniform_setter = UniformSetter {
-            context: Rc::clone(&program.context),
+            context: Rc::clone(context),
         };
         Self {
-            program,
+            context: Rc::clone(context),
+            uniforms,
             uniform_setter,
         }
     }
@@ -307,8 +689,7 @@ impl<'a> ProgramInUse<'a> {
 
 impl ProgramInUse<'_> {
     pub fn draw_arrays(&self, primitive_type: PrimitiveType, first: u32, count: u32) {
-        self.program
-            .context
+        self.context
             .draw_arrays(primitive_type.into(), first as i32, count as i32);
     }
     pub fn draw_arrays_instanced(
@@ -318,7 +699,7 @@ impl ProgramInUse<'_> {
         count: u32,
         instance_count: u32,
     ) {
-        self.program.context.draw_arrays_instanced(
+        self.context.draw_arrays_instanced(
             primitive_type.into(),
             first as i32,
             count as i32,
@@ -332,7 +713,7 @@ impl ProgramInUse<'_> {
         index_type: IndexType,
         offset: u32,
     ) {
-        self.program.context.draw_elements_with_i32(
+        self.context.draw_elements_with_i32(
             primitive_type.into(),
             count as i32,
             index_type.into(),
@@ -347,7 +728,7 @@ impl ProgramInUse<'_> {
         offset: u32,
         instance_count: u32,
     ) {
-        self.program.context.draw_elements_instanced_with_i32(
+        self.context.draw_elements_instanced_with_i32(
             primitive_type.into(),
             count as i32,
             index_type.into(),
@@ -364,7 +745,7 @@ impl ProgramInUse<'_> {
         index_type: IndexType,
         offset: u32,
     ) {
-        self.program.context.draw_range_elements_with_i32(
+        self.context.draw_range_elements_with_i32(
             primitive_type.into(),
             start,
             end,
@@ -375,61 +756,222 @@ impl ProgramInUse<'_> {
     }
 }
 
+impl ProgramInUse<'_> {
+    /// Binds `transform_feedback` and starts capturing the varyings declared when the program was
+    /// linked. `primitive_type` has to be POINTS, LINES or TRIANGLES and match the draw calls.
+    pub fn begin_transform_feedback(
+        &self,
+        transform_feedback: &GlTransformFeedback,
+        primitive_type: PrimitiveType,
+    ) {
+        transform_feedback.bind();
+        self.context.begin_transform_feedback(primitive_type.into());
+    }
+    pub fn pause_transform_feedback(&self) {
+        self.context.pause_transform_feedback();
+    }
+    pub fn resume_transform_feedback(&self) {
+        self.context.resume_transform_feedback();
+    }
+    pub fn end_transform_feedback(&self, transform_feedback: &GlTransformFeedback) {
+        self.context.end_transform_feedback();
+        transform_feedback.unbind();
+    }
+}
+
 impl ProgramInUse<'_> {
     pub fn push_uniform(&mut self, uniform_index: UniformIndex) {
-        self.program
-            .uniforms
+        self.uniforms
             .push_uniform(uniform_index, &self.uniform_setter);
     }
     pub fn push_all_uniforms(&mut self) {
-        self.program
-            .uniforms
-            .push_all_uniforms(&self.uniform_setter);
+        self.uniforms.push_all_uniforms(&self.uniform_setter);
     }
     pub fn push_uniform_by_name(&mut self, uniform_name: &str) {
-        self.program
-            .uniforms
+        self.uniforms
             .push_uniform_by_name(uniform_name, &self.uniform_setter);
     }
-    pub fn set_uniform(&mut self, uniform_index: UniformIndex, uniform_value: GlUniform) {
-        self.program
-            .uniforms
-            .set_uniform(uniform_index, uniform_value);
+    pub fn set_uniform(
+        &mut self,
+        uniform_index: UniformIndex,
+        uniform_value: GlUniform,
+    ) -> Result<(), UniformTypeMismatch> {
+        self.uniforms.set_uniform(uniform_index, uniform_value)?;
         self.push_uniform(uniform_index);
+        Ok(())
     }
-    pub fn set_uniform_by_name(&mut self, uniform_name: &str, uniform_value: GlUniform) {
-        if let Some(uniform_index) = self.program.uniforms.get_uniform_index(uniform_name) {
-            self.set_uniform(uniform_index, uniform_value);
+    pub fn set_uniform_by_name(
+        &mut self,
+        uniform_name: &str,
+        uniform_value: GlUniform,
+    ) -> Result<(), UniformTypeMismatch> {
+        if let Some(uniform_index) = self.uniforms.get_uniform_index(uniform_name) {
+            self.set_uniform(uniform_index, uniform_value)?;
         }
+        Ok(())
     }
 }
 
 impl Drop for ProgramInUse<'_> {
     fn drop(&mut self) {
-        self.program.unuse_program();
+        self.context.use_program(None);
     }
 }
 
 pub struct UniformData {
     value: GlUniform,
-    location: WebGlUniformLocation,
+    /// `None` once the uniform is no longer active after `ProgramUniforms::rebind`, the value is
+    /// kept but not pushed
+    location: Option<WebGlUniformLocation>,
     in_program: bool,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub struct UniformIndex(usize);
 
+/// Returned when a uniform is set with a value of a different type than the one it was
+/// inserted with, an array value for a single uniform (or the other way round) or an array of a
+/// different length. The stored value is left unchanged.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UniformTypeMismatch {
+    pub name: String,
+    pub expected: WebGLDataType,
+    pub got: WebGLDataType,
+    /// Array length of the uniform, `None` if it is not an array
+    pub expected_array_length: Option<u32>,
+    pub got_array_length: Option<u32>,
+}
+
+fn uniform_type_name(kind: WebGLDataType, array_length: Option<u32>) -> String {
+    match array_length {
+        Some(length) => format!("{}[{}]", kind.as_str(), length),
+        None => kind.as_str().to_string(),
+    }
+}
+
+impl fmt::Display for UniformTypeMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "uniform '{}' is {} but it was set with a {} value",
+            self.name,
+            uniform_type_name(self.expected, self.expected_array_length),
+            uniform_type_name(self.got, self.got_array_length)
+        )
+    }
+}
+
+static NEXT_UNIFORMS_ID: AtomicUsize = AtomicUsize::new(1);
+
+/// Uniform values of a program, a program shared by several materials has one set of uniforms
+/// per material
 pub struct ProgramUniforms {
+    id: usize,
     uniforms: Vec<UniformData>,
     keys: HashMap<String, UniformIndex>,
+    strict: bool,
 }
 
 impl ProgramUniforms {
-    fn new() -> Self {
+    pub(crate) fn new() -> Self {
         Self {
+            id: NEXT_UNIFORMS_ID.fetch_add(1, Ordering::Relaxed),
             uniforms: Vec::new(),
             keys: HashMap::new(),
+            strict: false,
+        }
+    }
+
+    /// Same as `GlProgram::insert_uniform` for uniforms stored outside of the program
+    pub fn insert_uniform(
+        &mut self,
+        program: &GlProgram,
+        uniform_name: &str,
+        uniform: GlUniform,
+    ) -> Result<UniformIndex, String> {
+        let uniform_data = program.uniform_data(uniform_name, uniform)?;
+        Ok(self.insert(uniform_name, uniform_data))
+    }
+
+    /// Inserts `value` checking `T::DATA_TYPE` against the type of the active uniform before
+    /// the value is converted. A uniform that is not active in `program` (removed by the
+    /// compiler) is kept without a location, like the uniforms `rebind` leaves inactive.
+    pub fn insert_value<T: IntoGlUniform>(
+        &mut self,
+        program: &GlProgram,
+        uniform_name: &str,
+        value: T,
+    ) -> Result<UniformIndex, UniformTypeMismatch> {
+        if let Err(error) = program.check_uniform_type(uniform_name, T::DATA_TYPE) {
+            return Err(self.mismatch(error));
+        }
+        Ok(self.insert_unchecked(program, uniform_name, value.uniform()))
+    }
+
+    // Inserts a value whose type was already checked, without a location if it is not active
+    pub(crate) fn insert_unchecked(
+        &mut self,
+        program: &GlProgram,
+        uniform_name: &str,
+        uniform: GlUniform,
+    ) -> UniformIndex {
+        let uniform_data = UniformData {
+            value: uniform,
+            location: program.get_uniform_location(uniform_name),
+            in_program: false,
+        };
+        self.insert(uniform_name, uniform_data)
+    }
+
+    /// See `GlProgram::set_strict_uniforms`
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    // Every type mismatch goes through here so strict mode can panic on it
+    fn mismatch(&self, error: UniformTypeMismatch) -> UniformTypeMismatch {
+        if self.strict && cfg!(debug_assertions) {
+            panic!("{}", error);
         }
+        error
+    }
+
+    fn uniform_name(&self, uniform_index: UniformIndex) -> String {
+        self.keys
+            .iter()
+            .find(|(_, index)| **index == uniform_index)
+            .map(|(name, _)| name.clone())
+            .unwrap_or_default()
+    }
+
+    fn set_all_dirty(&mut self) {
+        for uniform in self.uniforms.iter_mut() {
+            uniform.in_program = false;
+        }
+    }
+
+    /// Resolves the location of every uniform again by name in `program`, used when the program
+    /// is replaced. Indices and values are kept, uniforms that are not active in `program` or
+    /// whose active type no longer matches their value are kept without a location.
+    pub fn rebind(&mut self, program: &GlProgram) {
+        for (name, index) in self.keys.iter() {
+            let uniform = &mut self.uniforms[index.0];
+            let type_matches = match program.reflection.uniform(name).and_then(|a| a.kind) {
+                Some(active_kind) => uniform.value.matches_data_type(active_kind),
+                None => true,
+            };
+            uniform.location = if type_matches {
+                program.get_uniform_location(name)
+            } else {
+                None
+            };
+            uniform.in_program = false;
+        }
+    }
+
+    /// Whether the uniform has a location in the program it was last bound to
+    pub fn is_active(&self, index: UniformIndex) -> bool {
+        self.uniforms[index.0].location.is_some()
     }
 
     fn insert(&mut self, uniform_name: &str, uniform_data: UniformData) -> UniformIndex {
@@ -450,7 +992,10 @@ impl ProgramUniforms {
     }
 
     pub fn get_uniform_location(&self, index: UniformIndex) -> WebGlUniformLocation {
-        self.uniforms[index.0].location.clone()
+        self.uniforms[index.0]
+            .location
+            .clone()
+            .expect("Uniform is not active in the program")
     }
 
     pub fn get_uniform_index(&self, uniform_name: &str) -> Option<UniformIndex> {
@@ -463,7 +1008,9 @@ impl ProgramUniforms {
     fn push_all_uniforms(&mut self, uniform_setter: &UniformSetter) {
         for uniform in self.uniforms.iter_mut() {
             if !uniform.in_program {
-                uniform.value.set_uniform(uniform_setter, &uniform.location);
+                if let Some(location) = &uniform.location {
+                    uniform.value.set_uniform(uniform_setter, location);
+                }
                 uniform.in_program = true;
             }
         }
@@ -472,7 +1019,9 @@ impl ProgramUniforms {
     fn push_uniform(&mut self, uniform_index: UniformIndex, uniform_setter: &UniformSetter) {
         let uniform = &mut self.uniforms[uniform_index.0];
         if !uniform.in_program {
-            uniform.value.set_uniform(uniform_setter, &uniform.location);
+            if let Some(location) = &uniform.location {
+                uniform.value.set_uniform(uniform_setter, location);
+            }
             uniform.in_program = true;
         }
     }
@@ -482,15 +1031,57 @@ impl ProgramUniforms {
         self.push_uniform(uniform_index, uniform_setter)
     }
 
-    pub fn set_uniform(&mut self, uniform_index: UniformIndex, uniform_value: GlUniform) {
+    /// Stores the value to be pushed on the next `push_uniform`, the value has to be of the
+    /// same type (and array length) as the one the uniform was inserted with
+    pub fn set_uniform(
+        &mut self,
+        uniform_index: UniformIndex,
+        uniform_value: GlUniform,
+    ) -> Result<(), UniformTypeMismatch> {
         let uniform_data = &mut self.uniforms[uniform_index.0];
-        if uniform_data.value.equal_variant(&uniform_value) {
+        let expected = uniform_data.value.data_type();
+        let got = uniform_value.data_type();
+        let expected_array_length = uniform_data.value.array_length();
+        let got_array_length = uniform_value.array_length();
+        if expected == got && expected_array_length == got_array_length {
             uniform_data.value = uniform_value;
             uniform_data.in_program = false;
+            return Ok(());
         }
+        Err(self.mismatch(UniformTypeMismatch {
+            name: self.uniform_name(uniform_index),
+            expected,
+            got,
+            expected_array_length,
+            got_array_length,
+        }))
     }
 
-    pub fn set_uniform_by_name(&mut self, uniform_name: &str, uniform_value: GlUniform) {
+    /// Same as `set_uniform`, `T::DATA_TYPE` is checked against the stored value before the
+    /// value is converted
+    pub fn set_uniform_value<T: IntoGlUniform>(
+        &mut self,
+        uniform_index: UniformIndex,
+        value: T,
+    ) -> Result<(), UniformTypeMismatch> {
+        let stored = &self.uniforms[uniform_index.0].value;
+        if stored.data_type() != T::DATA_TYPE {
+            return Err(self.mismatch(UniformTypeMismatch {
+                name: self.uniform_name(uniform_index),
+                expected: stored.data_type(),
+                got: T::DATA_TYPE,
+                expected_array_length: stored.array_length(),
+                got_array_length: None,
+            }));
+        }
+        self.set_uniform(uniform_index, value.uniform())
+    }
+
+    pub fn set_uniform_by_name(
+        &mut self,
+        uniform_name: &str,
+        uniform_value: GlUniform,
+    ) -> Result<(), UniformTypeMismatch> {
         let uniform_index = *self.keys.get(uniform_name).expect("Uniform does not exist");
         self.set_uniform(uniform_index, uniform_value)
     }
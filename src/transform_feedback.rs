@@ -0,0 +1,73 @@
+use std::rc::Rc;
+
+use wasm_bindgen::JsValue;
+use web_sys::{WebGl2RenderingContext as gl, WebGlTransformFeedback};
+
+use crate::{BindingPoint, GlBuffer, Graphics};
+
+/// Transform feedback object, it stores which buffers receive the varyings captured by
+/// `ProgramInUse::begin_transform_feedback`
+pub struct GlTransformFeedback {
+    context: Rc<gl>,
+    pub transform_feedback: WebGlTransformFeedback,
+}
+
+impl Drop for GlTransformFeedback {
+    fn drop(&mut self) {
+        self.context
+            .delete_transform_feedback(Some(&self.transform_feedback));
+    }
+}
+
+impl GlTransformFeedback {
+    pub fn new(graphics: &Graphics) -> Result<Self, JsValue> {
+        match graphics.gl_context.create_transform_feedback() {
+            Some(transform_feedback) => Ok(Self {
+                context: graphics.gl_context.clone(),
+                transform_feedback,
+            }),
+            None => Err(JsValue::from("Could not create transform feedback object")),
+        }
+    }
+
+    pub fn bind(&self) {
+        self.context
+            .bind_transform_feedback(gl::TRANSFORM_FEEDBACK, Some(&self.transform_feedback));
+    }
+
+    pub fn unbind(&self) {
+        self.context
+            .bind_transform_feedback(gl::TRANSFORM_FEEDBACK, None);
+    }
+
+    /// Sets the buffer written by the captured varying `index` (in `Separate` mode) or by every
+    /// varying (index 0 in `Interleaved` mode)
+    pub fn set_buffer(&self, index: u32, buffer: &GlBuffer) {
+        self.bind();
+        self.context.bind_buffer_base(
+            BindingPoint::TRANSFORM_FEEDBACK_BUFFER.into(),
+            index,
+            Some(&buffer.buffer),
+        );
+        self.unbind();
+        // The indexed binding also changes the generic TRANSFORM_FEEDBACK_BUFFER binding,
+        // the buffer cannot be bound anywhere else while transform feedback is active
+        self.context
+            .bind_buffer(BindingPoint::TRANSFORM_FEEDBACK_BUFFER.into(), None);
+    }
+
+    /// Same as `set_buffer` but only `size` bytes starting at `offset` are written
+    pub fn set_buffer_range(&self, index: u32, buffer: &GlBuffer, offset: u32, size: u32) {
+        self.bind();
+        self.context.bind_buffer_range_with_i32_and_i32(
+            BindingPoint::TRANSFORM_FEEDBACK_BUFFER.into(),
+            index,
+            Some(&buffer.buffer),
+            offset as i32,
+            size as i32,
+        );
+        self.unbind();
+        self.context
+            .bind_buffer(BindingPoint::TRANSFORM_FEEDBACK_BUFFER.into(), None);
+    }
+}
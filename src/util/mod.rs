@@ -1,67 +1,243 @@
-use color::{RGBA, RGB};
+use color::{HSLA, RGBA, RGB};
 use glam::*;
-use crate::{GlUniform, FloatUniform, UIntUniform, IntUniform};
+use crate::{GlUniform, FloatUniform, UIntUniform, IntUniform, BoolUniform, WebGLDataType};
 
+/// Values that can be stored in a `GlUniform`. `DATA_TYPE` is the GLSL type of the value (of
+/// each element for slices and arrays), `GlMaterial::insert_uniform` checks it against the type
+/// of the active uniform and `GlMaterial::set_uniform_value` against the stored value, before the
+/// value is converted
 pub trait IntoGlUniform{
+	const DATA_TYPE: WebGLDataType;
 	fn uniform(&self)->GlUniform;
 }
 
 impl IntoGlUniform for f32{
+    const DATA_TYPE: WebGLDataType = WebGLDataType::Float;
     fn uniform(&self)->GlUniform {
         FloatUniform::Scalar(*self).into()
     }
 }
 
 impl IntoGlUniform for u32{
+    const DATA_TYPE: WebGLDataType = WebGLDataType::Uint;
     fn uniform(&self)->GlUniform {
         UIntUniform::Scalar(*self).into()
     }
 }
 
 impl IntoGlUniform for i32{
+    const DATA_TYPE: WebGLDataType = WebGLDataType::Int;
     fn uniform(&self)->GlUniform {
         IntUniform::Scalar(*self).into()
     }
 }
 
 impl IntoGlUniform for Vec4{
+    const DATA_TYPE: WebGLDataType = WebGLDataType::Vec4;
     fn uniform(&self)->GlUniform {
         FloatUniform::Vec4(*self).into()
     }
 }
 
 impl IntoGlUniform for Vec3{
+    const DATA_TYPE: WebGLDataType = WebGLDataType::Vec3;
     fn uniform(&self)->GlUniform {
         FloatUniform::Vec3(*self).into()
     }
 }
 
 impl IntoGlUniform for Mat4{
+    const DATA_TYPE: WebGLDataType = WebGLDataType::Mat4;
     fn uniform(&self)->GlUniform {
         FloatUniform::Mat4(*self).into()
     }
 }
 
 impl IntoGlUniform for Vec2{
+    const DATA_TYPE: WebGLDataType = WebGLDataType::Vec2;
     fn uniform(&self)->GlUniform {
         FloatUniform::Vec2(*self).into()
     }
 }
 
 impl IntoGlUniform for UVec2{
+    const DATA_TYPE: WebGLDataType = WebGLDataType::UVec2;
     fn uniform(&self)->GlUniform {
         UIntUniform::Vec2(*self).into()
     }
 }
 
+impl IntoGlUniform for Vec3A{
+    const DATA_TYPE: WebGLDataType = WebGLDataType::Vec3;
+    fn uniform(&self)->GlUniform {
+        FloatUniform::Vec3(Vec3::from(*self)).into()
+    }
+}
+
+impl IntoGlUniform for Mat2{
+    const DATA_TYPE: WebGLDataType = WebGLDataType::Mat2;
+    fn uniform(&self)->GlUniform {
+        FloatUniform::Mat2(*self).into()
+    }
+}
+
+impl IntoGlUniform for Mat3{
+    const DATA_TYPE: WebGLDataType = WebGLDataType::Mat3;
+    fn uniform(&self)->GlUniform {
+        FloatUniform::Mat3(*self).into()
+    }
+}
+
+impl IntoGlUniform for IVec2{
+    const DATA_TYPE: WebGLDataType = WebGLDataType::IVec2;
+    fn uniform(&self)->GlUniform {
+        IntUniform::Vec2(*self).into()
+    }
+}
+
+impl IntoGlUniform for IVec3{
+    const DATA_TYPE: WebGLDataType = WebGLDataType::IVec3;
+    fn uniform(&self)->GlUniform {
+        IntUniform::Vec3(*self).into()
+    }
+}
+
+impl IntoGlUniform for IVec4{
+    const DATA_TYPE: WebGLDataType = WebGLDataType::IVec4;
+    fn uniform(&self)->GlUniform {
+        IntUniform::Vec4(*self).into()
+    }
+}
+
+impl IntoGlUniform for UVec3{
+    const DATA_TYPE: WebGLDataType = WebGLDataType::UVec3;
+    fn uniform(&self)->GlUniform {
+        UIntUniform::Vec3(*self).into()
+    }
+}
+
+impl IntoGlUniform for UVec4{
+    const DATA_TYPE: WebGLDataType = WebGLDataType::UVec4;
+    fn uniform(&self)->GlUniform {
+        UIntUniform::Vec4(*self).into()
+    }
+}
+
+/// Uploaded as a vec4 with the `w` component last
+impl IntoGlUniform for Quat{
+    const DATA_TYPE: WebGLDataType = WebGLDataType::Vec4;
+    fn uniform(&self)->GlUniform {
+        FloatUniform::Vec4(Vec4::from(*self)).into()
+    }
+}
+
+impl IntoGlUniform for Affine3A{
+    const DATA_TYPE: WebGLDataType = WebGLDataType::Mat4;
+    fn uniform(&self)->GlUniform {
+        FloatUniform::Mat4(Mat4::from(*self)).into()
+    }
+}
+
 impl IntoGlUniform for RGBA{
+	const DATA_TYPE: WebGLDataType = WebGLDataType::Vec4;
 	fn uniform(&self)->GlUniform {
 		FloatUniform::Vec4(Vec4::from_array((*self).into())).into()
 	}
 }
 
 impl IntoGlUniform for RGB{
+	const DATA_TYPE: WebGLDataType = WebGLDataType::Vec3;
 	fn uniform(&self)->GlUniform {
 		FloatUniform::Vec3(Vec3::from_array((*self).into())).into()
 	}
-}
\ No newline at end of file
+}
+
+/// Converted to RGBA before the upload, like `RGBA` it is a vec4
+impl IntoGlUniform for HSLA{
+	const DATA_TYPE: WebGLDataType = WebGLDataType::Vec4;
+	fn uniform(&self)->GlUniform {
+		let rgba = RGBA::from(HSLA { h: self.h, s: self.s, l: self.l, a: self.a });
+		rgba.uniform()
+	}
+}
+
+impl IntoGlUniform for bool{
+    const DATA_TYPE: WebGLDataType = WebGLDataType::Bool;
+    fn uniform(&self)->GlUniform {
+        BoolUniform::Scalar(*self).into()
+    }
+}
+
+impl IntoGlUniform for BVec2{
+    const DATA_TYPE: WebGLDataType = WebGLDataType::BVec2;
+    fn uniform(&self)->GlUniform {
+        BoolUniform::Vec2(*self).into()
+    }
+}
+
+impl IntoGlUniform for BVec3{
+    const DATA_TYPE: WebGLDataType = WebGLDataType::BVec3;
+    fn uniform(&self)->GlUniform {
+        BoolUniform::Vec3(*self).into()
+    }
+}
+
+impl IntoGlUniform for BVec4{
+    const DATA_TYPE: WebGLDataType = WebGLDataType::BVec4;
+    fn uniform(&self)->GlUniform {
+        BoolUniform::Vec4(*self).into()
+    }
+}
+
+// Slices become uniform arrays, fixed arrays and vectors use the slice implementation
+macro_rules! impl_into_gl_uniform_slice {
+    ($type:ty, $uniform:ident::$variant:ident) => {
+        impl IntoGlUniform for [$type]{
+            const DATA_TYPE: WebGLDataType = <$type as IntoGlUniform>::DATA_TYPE;
+            fn uniform(&self)->GlUniform {
+                $uniform::$variant(self.to_vec()).into()
+            }
+        }
+    };
+}
+
+impl_into_gl_uniform_slice!(f32, FloatUniform::ScalarArray);
+impl_into_gl_uniform_slice!(Vec2, FloatUniform::Vec2Array);
+impl_into_gl_uniform_slice!(Vec3, FloatUniform::Vec3Array);
+impl_into_gl_uniform_slice!(Vec4, FloatUniform::Vec4Array);
+impl_into_gl_uniform_slice!(Mat2, FloatUniform::Mat2Array);
+impl_into_gl_uniform_slice!(Mat3, FloatUniform::Mat3Array);
+impl_into_gl_uniform_slice!(Mat4, FloatUniform::Mat4Array);
+impl_into_gl_uniform_slice!(i32, IntUniform::ScalarArray);
+impl_into_gl_uniform_slice!(IVec2, IntUniform::Vec2Array);
+impl_into_gl_uniform_slice!(IVec3, IntUniform::Vec3Array);
+impl_into_gl_uniform_slice!(IVec4, IntUniform::Vec4Array);
+impl_into_gl_uniform_slice!(u32, UIntUniform::ScalarArray);
+impl_into_gl_uniform_slice!(UVec2, UIntUniform::Vec2Array);
+impl_into_gl_uniform_slice!(UVec3, UIntUniform::Vec3Array);
+impl_into_gl_uniform_slice!(UVec4, UIntUniform::Vec4Array);
+impl_into_gl_uniform_slice!(bool, BoolUniform::ScalarArray);
+impl_into_gl_uniform_slice!(BVec2, BoolUniform::Vec2Array);
+impl_into_gl_uniform_slice!(BVec3, BoolUniform::Vec3Array);
+impl_into_gl_uniform_slice!(BVec4, BoolUniform::Vec4Array);
+
+impl<T, const N: usize> IntoGlUniform for [T; N] where [T]: IntoGlUniform{
+    const DATA_TYPE: WebGLDataType = <[T] as IntoGlUniform>::DATA_TYPE;
+    fn uniform(&self)->GlUniform {
+        self[..].uniform()
+    }
+}
+
+impl<T> IntoGlUniform for Vec<T> where [T]: IntoGlUniform{
+    const DATA_TYPE: WebGLDataType = <[T] as IntoGlUniform>::DATA_TYPE;
+    fn uniform(&self)->GlUniform {
+        self[..].uniform()
+    }
+}
+
+impl<T: IntoGlUniform + ?Sized> IntoGlUniform for &T{
+    const DATA_TYPE: WebGLDataType = T::DATA_TYPE;
+    fn uniform(&self)->GlUniform {
+        (**self).uniform()
+    }
+}
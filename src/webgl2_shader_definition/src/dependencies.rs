@@ -0,0 +1,249 @@
+use std::{collections::HashMap, fmt};
+
+use crate::{FunctionDefinition, FunctionDefinitionType};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DependencyError {
+    MissingFunction { name: String },
+    Cycle { functions: Vec<String> },
+}
+
+impl fmt::Display for DependencyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DependencyError::MissingFunction { name } => {
+                write!(f, "function '{}' is not defined", name)
+            }
+            DependencyError::Cycle { functions } => {
+                write!(f, "functions call each other in a cycle: {}", functions.join(" -> "))
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum VisitState {
+    Visiting,
+    Done,
+}
+
+/// Returns the identifiers in `body` that are followed by an opening parenthesis, skipping comments
+fn called_identifiers(body: &str) -> Vec<&str> {
+    let bytes = body.as_bytes();
+    let mut identifiers = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        if c == b'/' && bytes.get(i + 1) == Some(&b'/') {
+            while i < bytes.len() && bytes[i] != b'\n' {
+                i += 1;
+            }
+        } else if c == b'/' && bytes.get(i + 1) == Some(&b'*') {
+            i += 2;
+            while i < bytes.len() && !(bytes[i] == b'*' && bytes.get(i + 1) == Some(&b'/')) {
+                i += 1;
+            }
+            i += 2;
+        } else if c.is_ascii_alphabetic() || c == b'_' {
+            let start = i;
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                i += 1;
+            }
+            let identifier = &body[start..i];
+            let mut next = i;
+            while next < bytes.len() && bytes[next].is_ascii_whitespace() {
+                next += 1;
+            }
+            if bytes.get(next) == Some(&b'(') {
+                identifiers.push(identifier);
+            }
+        } else if c.is_ascii_digit() {
+            // Skips numeric literals so suffixes like `1u` are not read as identifiers
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'.') {
+                i += 1;
+            }
+        } else {
+            i += 1;
+        }
+    }
+    identifiers
+}
+
+/// Names of the functions inside `available` that are called from the body of `function`
+pub fn function_dependencies<'a>(
+    function: &FunctionDefinition,
+    available: &'a [FunctionDefinition],
+) -> Vec<&'a str> {
+    let mut dependencies: Vec<&'a str> = Vec::new();
+    if let FunctionDefinitionType::InlineFn { body, .. } = &function.definition {
+        for identifier in called_identifiers(body) {
+            if let Some(callee) = available.iter().find(|def| def.name == identifier) {
+                if !dependencies.contains(&callee.name.as_str()) {
+                    dependencies.push(&callee.name);
+                }
+            }
+        }
+    }
+    dependencies
+}
+
+fn visit_function<'a>(
+    name: &str,
+    available: &'a [FunctionDefinition],
+    states: &mut HashMap<&'a str, VisitState>,
+    path: &mut Vec<&'a str>,
+    sorted: &mut Vec<&'a FunctionDefinition>,
+) -> Result<(), DependencyError> {
+    let function = match available.iter().find(|def| def.name == name) {
+        Some(function) => function,
+        None => {
+            return Err(DependencyError::MissingFunction {
+                name: name.to_string(),
+            })
+        }
+    };
+
+    match states.get(function.name.as_str()) {
+        Some(VisitState::Done) => return Ok(()),
+        Some(VisitState::Visiting) => {
+            let cycle_start = path
+                .iter()
+                .position(|fn_name| *fn_name == function.name)
+                .unwrap_or(0);
+            let mut functions: Vec<String> =
+                path[cycle_start..].iter().map(|fn_name| fn_name.to_string()).collect();
+            functions.push(function.name.clone());
+            return Err(DependencyError::Cycle { functions });
+        }
+        None => {}
+    }
+
+    states.insert(&function.name, VisitState::Visiting);
+    path.push(&function.name);
+    for dependency in function_dependencies(function, available) {
+        visit_function(dependency, available, states, path, sorted)?;
+    }
+    path.pop();
+    states.insert(&function.name, VisitState::Done);
+    sorted.push(function);
+    Ok(())
+}
+
+/// Collects the functions named in `import_fn` together with every function they call (directly or
+/// through other functions) from `available`. The result has no duplicates and is sorted so that
+/// every function comes after the functions it calls, GLSL requires this order.
+pub fn resolve_function_dependencies<'a, S: AsRef<str>>(
+    import_fn: &[S],
+    available: &'a [FunctionDefinition],
+) -> Result<Vec<&'a FunctionDefinition>, DependencyError> {
+    let mut states = HashMap::new();
+    let mut path = Vec::new();
+    let mut sorted = Vec::new();
+    for name in import_fn {
+        visit_function(name.as_ref(), available, &mut states, &mut path, &mut sorted)?;
+    }
+    Ok(sorted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shader_functions::lighting_functions::get_pbr_functions;
+
+    fn inline_fn(name: &str, body: &str) -> FunctionDefinition {
+        FunctionDefinition {
+            name: name.into(),
+            definition: FunctionDefinitionType::InlineFn {
+                return_type: crate::WebGLDataType::Float,
+                parameters: "float x".into(),
+                body: body.into(),
+            },
+        }
+    }
+
+    fn names(functions: &[&FunctionDefinition]) -> Vec<String> {
+        functions
+            .iter()
+            .map(|function| function.name.clone())
+            .collect()
+    }
+
+    #[test]
+    fn pbr_lighting_pulls_in_its_dependencies_in_order() {
+        let pbr = get_pbr_functions();
+        let sorted = resolve_function_dependencies(&["PBRLighting"], &pbr).unwrap();
+        assert_eq!(
+            names(&sorted),
+            [
+                "DistributionGGX",
+                "GeometrySchlickGGX",
+                "GeometrySmith",
+                "fresnelSchlick",
+                "PBRLighting"
+            ]
+        );
+    }
+
+    #[test]
+    fn shared_dependencies_are_not_duplicated() {
+        let pbr = get_pbr_functions();
+        let sorted =
+            resolve_function_dependencies(&["GeometrySmith", "PBRLighting", "GeometrySmith"], &pbr)
+                .unwrap();
+        assert_eq!(
+            names(&sorted),
+            [
+                "GeometrySchlickGGX",
+                "GeometrySmith",
+                "DistributionGGX",
+                "fresnelSchlick",
+                "PBRLighting"
+            ]
+        );
+    }
+
+    #[test]
+    fn cycle_is_reported() {
+        let functions = [
+            inline_fn("a", "return b(x);"),
+            inline_fn("b", "return a(x);"),
+        ];
+        assert_eq!(
+            resolve_function_dependencies(&["a"], &functions).unwrap_err(),
+            DependencyError::Cycle {
+                functions: vec!["a".into(), "b".into(), "a".into()]
+            }
+        );
+    }
+
+    #[test]
+    fn missing_function_is_reported() {
+        let functions = [inline_fn("a", "return x;")];
+        assert_eq!(
+            resolve_function_dependencies(&["b"], &functions).unwrap_err(),
+            DependencyError::MissingFunction { name: "b".into() }
+        );
+    }
+
+    #[test]
+    fn calls_in_comments_and_number_literals_are_ignored() {
+        let body = r#"
+// b(x) is slower
+/* c(x); */
+uint i = 4u (1u);
+float y = 1e (2.0);
+return d (x);"#;
+        assert_eq!(called_identifiers(body), ["d"]);
+
+        let functions = [
+            inline_fn("a", body),
+            inline_fn("b", "return x;"),
+            inline_fn("c", "return x;"),
+            inline_fn("u", "return x;"),
+            inline_fn("e", "return x;"),
+            inline_fn("d", "return x;"),
+        ];
+        let sorted = resolve_function_dependencies(&["a"], &functions).unwrap();
+        assert_eq!(names(&sorted), ["d", "a"]);
+    }
+}
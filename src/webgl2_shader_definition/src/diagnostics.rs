@@ -0,0 +1,346 @@
+use std::fmt;
+
+use crate::ShaderStageKind;
+
+/// The part of a `ShaderSource` a line of generated GLSL comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShaderSection {
+    /// `#version`, header lines, keyword defines and precision qualifiers
+    Header,
+    Attribute(String),
+    Varying(String),
+    Struct(String),
+    UniformBlock(String),
+    Uniform(String),
+    ImportedFunction(String),
+    LocalImport,
+    MainFn,
+}
+
+impl fmt::Display for ShaderSection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShaderSection::Header => write!(f, "header"),
+            ShaderSection::Attribute(name) => write!(f, "attribute '{}'", name),
+            ShaderSection::Varying(name) => write!(f, "varying '{}'", name),
+            ShaderSection::Struct(name) => write!(f, "struct '{}'", name),
+            ShaderSection::UniformBlock(name) => write!(f, "uniform block '{}'", name),
+            ShaderSection::Uniform(name) => write!(f, "uniform '{}'", name),
+            ShaderSection::ImportedFunction(name) => write!(f, "imported function '{}'", name),
+            ShaderSection::LocalImport => write!(f, "local_import"),
+            ShaderSection::MainFn => write!(f, "main_fn"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct LineSpan {
+    /// First generated line of the span, starting at 1
+    start: u32,
+    line_count: u32,
+    section: ShaderSection,
+    /// Whether the lines were written by the author of the source, in that case they can be
+    /// numbered inside the section
+    authored: bool,
+}
+
+/// Where a line of generated GLSL comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLocation {
+    pub section: ShaderSection,
+    /// Line inside the text of the section (`main_fn`, a function body, `local_import`),
+    /// starting at 1. `None` for the lines added by the generator.
+    pub section_line: Option<u32>,
+}
+
+/// Records the section of every line of a generated shader stage
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LineMap {
+    spans: Vec<LineSpan>,
+}
+
+impl LineMap {
+    /// Location of a generated line, lines start at 1 like in the GLSL info log
+    pub fn locate(&self, line: u32) -> Option<SourceLocation> {
+        self.spans
+            .iter()
+            .find(|span| line >= span.start && line < span.start + span.line_count)
+            .map(|span| SourceLocation {
+                section: span.section.clone(),
+                section_line: span.authored.then(|| line - span.start + 1),
+            })
+    }
+}
+
+/// GLSL generated for one stage with the map of where each line comes from
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GeneratedShader {
+    pub code: String,
+    pub line_map: LineMap,
+}
+
+impl GeneratedShader {
+    /// Code without a line map, diagnostics of these shaders have no section
+    pub fn from_code(code: String) -> Self {
+        Self {
+            code,
+            line_map: LineMap::default(),
+        }
+    }
+
+    /// Text of a generated line, lines start at 1
+    pub fn line(&self, line: u32) -> Option<&str> {
+        self.code.lines().nth(line.checked_sub(1)? as usize)
+    }
+}
+
+/// Builds a `GeneratedShader`, every piece of text is written with the section it belongs to
+pub(crate) struct ShaderWriter {
+    code: String,
+    line_map: LineMap,
+}
+
+impl ShaderWriter {
+    pub(crate) fn new() -> Self {
+        Self {
+            code: String::new(),
+            line_map: LineMap::default(),
+        }
+    }
+
+    fn write(&mut self, section: ShaderSection, text: &str, authored: bool) {
+        if text.is_empty() {
+            return;
+        }
+        let start = self.code.matches('\n').count() as u32 + 1;
+        self.code.push_str(text);
+        let mut line_count = text.matches('\n').count() as u32;
+        if !text.ends_with('\n') {
+            line_count += 1;
+        }
+        self.line_map.spans.push(LineSpan {
+            start,
+            line_count,
+            section,
+            authored,
+        });
+    }
+
+    /// Text added by the generator
+    pub(crate) fn push(&mut self, section: ShaderSection, text: &str) {
+        self.write(section, text, false)
+    }
+
+    /// Text copied from the source, its lines are numbered inside the section
+    pub(crate) fn push_authored(&mut self, section: ShaderSection, text: &str) {
+        self.write(section, text, true)
+    }
+
+    pub(crate) fn finish(self) -> GeneratedShader {
+        GeneratedShader {
+            code: self.code,
+            line_map: self.line_map,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagnosticSeverity {
+    Error,
+    Warning,
+}
+
+/// A message of the GLSL compiler info log, located in the `ShaderSource` when the shader was
+/// generated with a line map
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShaderDiagnostic {
+    pub stage: ShaderStageKind,
+    pub severity: DiagnosticSeverity,
+    /// Line of the generated GLSL, `None` for messages that are not about a line
+    pub line: Option<u32>,
+    pub location: Option<SourceLocation>,
+    pub message: String,
+    /// The generated line the message is about
+    pub snippet: Option<String>,
+}
+
+impl fmt::Display for ShaderDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            DiagnosticSeverity::Error => "error",
+            DiagnosticSeverity::Warning => "warning",
+        };
+        write!(f, "{} {}", self.stage.as_str(), severity)?;
+        match (&self.location, self.line) {
+            (Some(location), _) => {
+                write!(f, " in {}", location.section)?;
+                if let Some(section_line) = location.section_line {
+                    write!(f, " line {}", section_line)?;
+                }
+            }
+            (None, Some(line)) => write!(f, " at line {}", line)?,
+            (None, None) => {}
+        }
+        write!(f, ": {}", self.message)?;
+        if let Some(snippet) = &self.snippet {
+            write!(f, "\n    {}", snippet.trim())?;
+        }
+        Ok(())
+    }
+}
+
+// Splits "0:12: message" into the line and the message
+fn split_line_number(text: &str) -> Option<(u32, &str)> {
+    let mut parts = text.splitn(3, ':');
+    let _source_string = parts.next()?.trim().parse::<u32>().ok()?;
+    let line = parts.next()?.trim().parse::<u32>().ok()?;
+    Some((line, parts.next()?.trim()))
+}
+
+/// Parses a shader info log (`ERROR: 0:12: 'x' : undeclared identifier`) into diagnostics
+/// located with the line map of `shader`
+pub fn parse_info_log(
+    log: &str,
+    stage: ShaderStageKind,
+    shader: &GeneratedShader,
+) -> Vec<ShaderDiagnostic> {
+    let mut diagnostics = Vec::new();
+    for log_line in log.lines() {
+        let log_line = log_line.trim_matches(|c: char| c.is_whitespace() || c == '\0');
+        if log_line.is_empty() {
+            continue;
+        }
+        let (severity, text) = if let Some(text) = log_line.strip_prefix("ERROR:") {
+            (DiagnosticSeverity::Error, text)
+        } else if let Some(text) = log_line.strip_prefix("WARNING:") {
+            (DiagnosticSeverity::Warning, text)
+        } else {
+            (DiagnosticSeverity::Error, log_line)
+        };
+        let diagnostic = match split_line_number(text) {
+            Some((line, message)) => ShaderDiagnostic {
+                stage,
+                severity,
+                line: Some(line),
+                location: shader.line_map.locate(line),
+                message: message.to_string(),
+                snippet: shader.line(line).map(|snippet| snippet.to_string()),
+            },
+            None => ShaderDiagnostic {
+                stage,
+                severity,
+                line: None,
+                location: None,
+                message: text.trim().to_string(),
+                snippet: None,
+            },
+        };
+        diagnostics.push(diagnostic);
+    }
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_shader() -> GeneratedShader {
+        let mut writer = ShaderWriter::new();
+        writer.push(
+            ShaderSection::Header,
+            "#version 300 es\n#define SKINNED\nprecision highp float;\nprecision highp int;\n",
+        );
+        writer.push(
+            ShaderSection::Uniform("u_color".to_string()),
+            "uniform vec4 u_color;\n",
+        );
+        writer.push(
+            ShaderSection::Uniform("u_time".to_string()),
+            "uniform float u_time;\n",
+        );
+        writer.push(ShaderSection::MainFn, "void main() {\n");
+        writer.push_authored(
+            ShaderSection::MainFn,
+            "vec4 color = u_color;\nfloat t = u_time;\ncolor.a *= t;\ncolor.rgb *= 0.5;\nout_color = colr;",
+        );
+        writer.push(ShaderSection::MainFn, "\n}");
+        writer.finish()
+    }
+
+    #[test]
+    fn line_map_locates_sections() {
+        let shader = sample_shader();
+        let uniform = shader.line_map.locate(6).unwrap();
+        assert_eq!(
+            uniform.section,
+            ShaderSection::Uniform("u_time".to_string())
+        );
+        assert_eq!(uniform.section_line, None);
+        let main_fn = shader.line_map.locate(8).unwrap();
+        assert_eq!(main_fn.section, ShaderSection::MainFn);
+        assert_eq!(main_fn.section_line, Some(1));
+        assert_eq!(shader.line_map.locate(0), None);
+        assert_eq!(shader.line_map.locate(20), None);
+    }
+
+    #[test]
+    fn info_log_is_located_in_the_source() {
+        let shader = sample_shader();
+        let log = "ERROR: 0:12: 'colr' : undeclared identifier\n\
+                   WARNING: 0:5: 'u_color' : unused uniform\n\
+                   ERROR: 1 compilation errors.  No code generated.\n\0";
+        let diagnostics = parse_info_log(log, ShaderStageKind::Fragment, &shader);
+        assert_eq!(diagnostics.len(), 3);
+
+        assert_eq!(
+            diagnostics[0],
+            ShaderDiagnostic {
+                stage: ShaderStageKind::Fragment,
+                severity: DiagnosticSeverity::Error,
+                line: Some(12),
+                location: Some(SourceLocation {
+                    section: ShaderSection::MainFn,
+                    section_line: Some(5),
+                }),
+                message: "'colr' : undeclared identifier".to_string(),
+                snippet: Some("out_color = colr;".to_string()),
+            }
+        );
+        assert_eq!(
+            diagnostics[0].to_string(),
+            "fragment error in main_fn line 5: 'colr' : undeclared identifier\n    out_color = colr;"
+        );
+
+        assert_eq!(diagnostics[1].severity, DiagnosticSeverity::Warning);
+        assert_eq!(
+            diagnostics[1].location,
+            Some(SourceLocation {
+                section: ShaderSection::Uniform("u_color".to_string()),
+                section_line: None,
+            })
+        );
+
+        assert_eq!(diagnostics[2].line, None);
+        assert_eq!(diagnostics[2].location, None);
+        assert_eq!(
+            diagnostics[2].message,
+            "1 compilation errors.  No code generated."
+        );
+    }
+
+    #[test]
+    fn shader_without_line_map_keeps_the_line() {
+        let shader = sample_shader();
+        let shader = GeneratedShader::from_code(shader.code);
+        let diagnostics = parse_info_log(
+            "ERROR: 0:12: 'colr' : undeclared identifier",
+            ShaderStageKind::Vertex,
+            &shader,
+        );
+        assert_eq!(diagnostics[0].location, None);
+        assert_eq!(
+            diagnostics[0].to_string(),
+            "vertex error at line 12: 'colr' : undeclared identifier\n    out_color = colr;"
+        );
+    }
+}
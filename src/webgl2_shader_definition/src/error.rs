@@ -0,0 +1,138 @@
+use std::{error::Error, fmt};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{DependencyError, ParameterError, ValidationError, VariantError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ShaderStageKind {
+    Vertex,
+    Fragment,
+}
+
+impl ShaderStageKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ShaderStageKind::Vertex => "vertex",
+            ShaderStageKind::Fragment => "fragment",
+        }
+    }
+}
+
+/// Errors returned by the shader generation functions, they describe the problem in terms of the
+/// `ShaderSource` so it can be reported to the author of the shader definition
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShaderGenError {
+    /// A stage `import_fn` entry names a function that is not in the imported functions
+    FunctionNotFound {
+        name: String,
+        stage: ShaderStageKind,
+    },
+    /// The imported functions of a stage call each other recursively, GLSL does not allow recursion
+    DependencyCycle {
+        functions: Vec<String>,
+        stage: ShaderStageKind,
+    },
+    /// A typed parameter of an inline function is not valid GLSL
+    InvalidParameter {
+        function: String,
+        stage: ShaderStageKind,
+        error: ParameterError,
+    },
+    /// An `ImportFn` definition names a file the `FunctionLibrary` does not have
+    UnresolvedImport { function: String, path: String },
+    /// The imported file does not define the function or the functions it calls
+    InvalidImport {
+        function: String,
+        path: String,
+        error: DependencyError,
+    },
+    /// Two different functions with the same name were imported, the paths of the files that
+    /// define them, `None` for a function of the source
+    ConflictingFunction {
+        function: String,
+        paths: [Option<String>; 2],
+    },
+    /// `ShaderSource::validate` found problems in the interface between the stages
+    InvalidSource(Vec<ValidationError>),
+    /// The selected variant does not match the keywords declared in the source
+    InvalidVariant(VariantError),
+}
+
+impl ShaderGenError {
+    pub(crate) fn from_dependency_error(error: DependencyError, stage: ShaderStageKind) -> Self {
+        match error {
+            DependencyError::MissingFunction { name } => {
+                ShaderGenError::FunctionNotFound { name, stage }
+            }
+            DependencyError::Cycle { functions } => {
+                ShaderGenError::DependencyCycle { functions, stage }
+            }
+        }
+    }
+}
+
+impl fmt::Display for ShaderGenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShaderGenError::FunctionNotFound { name, stage } => write!(
+                f,
+                "{} stage imports function '{}' but it is not defined in imported_functions",
+                stage.as_str(),
+                name
+            ),
+            ShaderGenError::DependencyCycle { functions, stage } => write!(
+                f,
+                "{} stage imports functions that call each other in a cycle: {}",
+                stage.as_str(),
+                functions.join(" -> ")
+            ),
+            ShaderGenError::InvalidParameter {
+                function,
+                stage,
+                error,
+            } => write!(
+                f,
+                "function '{}' imported by the {} stage has an invalid parameter: {}",
+                function,
+                stage.as_str(),
+                error
+            ),
+            ShaderGenError::UnresolvedImport { function, path } => write!(
+                f,
+                "function '{}' is imported from '{}' but the function library does not have it",
+                function, path
+            ),
+            ShaderGenError::InvalidImport {
+                function,
+                path,
+                error,
+            } => write!(
+                f,
+                "function '{}' cannot be imported from '{}': {}",
+                function, path, error
+            ),
+            ShaderGenError::ConflictingFunction { function, paths } => {
+                let [first, second] = paths.each_ref().map(|path| match path {
+                    Some(path) => format!("'{}'", path),
+                    None => "the shader source".to_string(),
+                });
+                write!(
+                    f,
+                    "function '{}' is defined both in {} and in {}",
+                    function, first, second
+                )
+            }
+            ShaderGenError::InvalidSource(errors) => {
+                write!(f, "shader source is not valid:")?;
+                for error in errors {
+                    write!(f, "\n - {}", error)?;
+                }
+                Ok(())
+            }
+            ShaderGenError::InvalidVariant(error) => write!(f, "invalid shader variant: {}", error),
+        }
+    }
+}
+
+impl Error for ShaderGenError {}
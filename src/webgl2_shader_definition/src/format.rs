@@ -0,0 +1,194 @@
+use std::{error::Error, fmt, path::Path, str::FromStr};
+
+use serde::de::DeserializeOwned;
+
+use crate::{FunctionImports, ShaderSource};
+
+/// Text format of a shader source or of a function file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ShaderFormat {
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl ShaderFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ShaderFormat::Toml => "toml",
+            ShaderFormat::Yaml => "yaml",
+            ShaderFormat::Json => "json",
+        }
+    }
+
+    /// Format of a file extension (`toml`, `yaml`, `yml`, `json`), case insensitive
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_ascii_lowercase().as_str() {
+            "toml" => Some(ShaderFormat::Toml),
+            "yaml" | "yml" => Some(ShaderFormat::Yaml),
+            "json" => Some(ShaderFormat::Json),
+            _ => None,
+        }
+    }
+
+    /// Format of the extension of `path`, works for urls too
+    pub fn from_path(path: &str) -> Option<Self> {
+        Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(Self::from_extension)
+    }
+
+    /// The explicit format if there is one, otherwise the format of the extension of `path`,
+    /// otherwise `default`
+    pub fn detect(path: &str, explicit: Option<ShaderFormat>, default: ShaderFormat) -> Self {
+        explicit
+            .or_else(|| Self::from_path(path))
+            .unwrap_or(default)
+    }
+
+    pub fn parse<T: DeserializeOwned>(&self, text: &str) -> Result<T, FormatError> {
+        match self {
+            ShaderFormat::Toml => toml::from_str(text).map_err(FormatError::Toml),
+            ShaderFormat::Yaml => serde_yaml::from_str(text).map_err(FormatError::Yaml),
+            ShaderFormat::Json => serde_json::from_str(text).map_err(FormatError::Json),
+        }
+    }
+
+    pub fn parse_source(&self, text: &str) -> Result<ShaderSource, FormatError> {
+        self.parse(text)
+    }
+
+    pub fn parse_function_imports(&self, text: &str) -> Result<FunctionImports, FormatError> {
+        self.parse(text)
+    }
+}
+
+impl FromStr for ShaderFormat {
+    type Err = FormatError;
+
+    /// Same names as the extensions, used by the explicit format argument of the proc macros
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        Self::from_extension(format).ok_or_else(|| FormatError::UnknownFormat(format.to_string()))
+    }
+}
+
+impl fmt::Display for ShaderFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[derive(Debug)]
+pub enum FormatError {
+    Toml(toml::de::Error),
+    Yaml(serde_yaml::Error),
+    Json(serde_json::Error),
+    /// The name does not match any `ShaderFormat`
+    UnknownFormat(String),
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormatError::Toml(error) => write!(f, "invalid TOML: {}", error),
+            FormatError::Yaml(error) => write!(f, "invalid YAML: {}", error),
+            FormatError::Json(error) => write!(f, "invalid JSON: {}", error),
+            FormatError::UnknownFormat(format) => write!(
+                f,
+                "unknown format '{}', expected toml, yaml, yml or json",
+                format
+            ),
+        }
+    }
+}
+
+impl Error for FormatError {}
+
+/// Parses a shader source of any format, the format is `format` if given, otherwise the one of
+/// the extension of `path`, otherwise TOML. `path` is only used to detect the format.
+pub fn load_shader_source(
+    path: &str,
+    text: &str,
+    format: Option<ShaderFormat>,
+) -> Result<ShaderSource, FormatError> {
+    ShaderFormat::detect(path, format, ShaderFormat::Toml).parse_source(text)
+}
+
+/// Parses a function file of any format, the format is `format` if given, otherwise the one of
+/// the extension of `path`, otherwise YAML, the format function files always had
+pub fn load_function_imports(
+    path: &str,
+    text: &str,
+    format: Option<ShaderFormat>,
+) -> Result<FunctionImports, FormatError> {
+    ShaderFormat::detect(path, format, ShaderFormat::Yaml).parse_function_imports(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_prefers_explicit_then_extension_then_default() {
+        assert_eq!(
+            ShaderFormat::detect("water.toml", Some(ShaderFormat::Json), ShaderFormat::Yaml),
+            ShaderFormat::Json
+        );
+        assert_eq!(
+            ShaderFormat::detect("water.toml", None, ShaderFormat::Yaml),
+            ShaderFormat::Toml
+        );
+        assert_eq!(
+            ShaderFormat::detect("water.def", None, ShaderFormat::Yaml),
+            ShaderFormat::Yaml
+        );
+        assert_eq!(
+            ShaderFormat::detect("water", None, ShaderFormat::Toml),
+            ShaderFormat::Toml
+        );
+    }
+
+    #[test]
+    fn extensions_are_case_insensitive() {
+        assert_eq!(
+            ShaderFormat::from_path("lights.yml"),
+            Some(ShaderFormat::Yaml)
+        );
+        assert_eq!(
+            ShaderFormat::from_path("lights.YAML"),
+            Some(ShaderFormat::Yaml)
+        );
+        assert_eq!(
+            ShaderFormat::from_path("/shaders/Water.Json"),
+            Some(ShaderFormat::Json)
+        );
+        assert_eq!(
+            ShaderFormat::from_path("https://example.com/shaders/water.TOML"),
+            Some(ShaderFormat::Toml)
+        );
+        assert_eq!(ShaderFormat::from_path("shaders.d/water"), None);
+        assert_eq!("YML".parse::<ShaderFormat>().unwrap(), ShaderFormat::Yaml);
+        assert!(matches!(
+            "glsl".parse::<ShaderFormat>(),
+            Err(FormatError::UnknownFormat(format)) if format == "glsl"
+        ));
+    }
+
+    #[test]
+    fn function_files_default_to_yaml() {
+        let yaml = "function_definitions: []\n";
+        assert!(load_function_imports("lights", yaml, None).is_ok());
+        assert!(
+            load_function_imports("lights.json", "{\"function_definitions\": []}", None).is_ok()
+        );
+        assert!(matches!(
+            load_function_imports("lights.json", yaml, None),
+            Err(FormatError::Json(_))
+        ));
+        assert!(matches!(
+            load_function_imports("lights", yaml, Some(ShaderFormat::Toml)),
+            Err(FormatError::Toml(_))
+        ));
+    }
+}
@@ -0,0 +1,363 @@
+use std::collections::HashMap;
+
+use crate::{
+    load_function_imports, resolve_function_dependencies,
+    shader_functions::{builtin_functions, BUILTIN_PREFIX},
+    DependencyError, FormatError, FunctionDefinition, FunctionDefinitionType, FunctionImports,
+    ShaderFormat, ShaderGenError,
+};
+
+/// Resolves the `ImportFn { path }` definitions of a `ShaderSource` when the shader is generated
+/// at runtime, the proc macros read the files from disk instead
+pub trait FunctionLibrary {
+    /// Function definitions of the file at `path`, `None` if the library does not have it
+    fn functions(&self, path: &str) -> Option<&[FunctionDefinition]>;
+}
+
+/// Function files kept in memory by path. Files can be inserted as definitions (for example the
+/// `shader_functions` modules) or as the text of a function file that was fetched.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryFunctionLibrary {
+    files: HashMap<String, Vec<FunctionDefinition>>,
+}
+
+impl MemoryFunctionLibrary {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds or replaces the file at `path`
+    pub fn insert(&mut self, path: &str, functions: Vec<FunctionDefinition>) {
+        self.files.insert(path.to_string(), functions);
+    }
+
+    pub fn insert_imports(&mut self, path: &str, imports: FunctionImports) {
+        self.insert(path, imports.function_definitions);
+    }
+
+    /// Parses the text of a function file, the format is detected from the extension of `path`
+    /// like in the proc macros, YAML if it has none
+    pub fn insert_str(&mut self, path: &str, file: &str) -> Result<(), FormatError> {
+        self.insert_imports(path, load_function_imports(path, file, None)?);
+        Ok(())
+    }
+
+    pub fn insert_str_with_format(
+        &mut self,
+        path: &str,
+        file: &str,
+        format: ShaderFormat,
+    ) -> Result<(), FormatError> {
+        self.insert_imports(path, format.parse_function_imports(file)?);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, path: &str) -> Option<Vec<FunctionDefinition>> {
+        self.files.remove(path)
+    }
+
+    pub fn contains(&self, path: &str) -> bool {
+        self.files.contains_key(path)
+    }
+
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        self.files.keys().map(|path| path.as_str())
+    }
+}
+
+impl FunctionLibrary for MemoryFunctionLibrary {
+    fn functions(&self, path: &str) -> Option<&[FunctionDefinition]> {
+        self.files.get(path).map(|functions| functions.as_slice())
+    }
+}
+
+/// Replaces every `ImportFn` of `functions` by the inline definition found in `library`, the
+/// functions it calls from the same file are added as well. Inline functions are copied.
+/// Paths starting with `builtin:` are resolved with `shader_functions::builtin_functions`
+/// whatever the library. A function file can import from other files, those paths are looked up
+/// in `library` the same way. A function reached through several imports is added once, two
+/// different functions with the same name are a `ShaderGenError::ConflictingFunction`.
+pub fn resolve_imported_functions(
+    functions: &[FunctionDefinition],
+    library: &dyn FunctionLibrary,
+) -> Result<Vec<FunctionDefinition>, ShaderGenError> {
+    let mut resolved: Vec<(Option<String>, FunctionDefinition)> = Vec::new();
+    resolve_functions(functions, None, library, &mut Vec::new(), &mut resolved)?;
+    Ok(resolved.into_iter().map(|(_, function)| function).collect())
+}
+
+// `path` is the file `functions` come from, `None` for the functions of the source. `importing`
+// holds the (path, function) imports being resolved, to stop on files that import from each
+// other. Resolved functions are kept with their path, a function is the same one only if both
+// match.
+fn resolve_functions(
+    functions: &[FunctionDefinition],
+    path: Option<&str>,
+    library: &dyn FunctionLibrary,
+    importing: &mut Vec<(String, String)>,
+    resolved: &mut Vec<(Option<String>, FunctionDefinition)>,
+) -> Result<(), ShaderGenError> {
+    for function in functions {
+        match &function.definition {
+            FunctionDefinitionType::InlineFn { .. } => {
+                let existing = resolved.iter().find(|(_, def)| def.name == function.name);
+                match existing {
+                    Some((existing_path, _)) if existing_path.as_deref() == path => {}
+                    Some((existing_path, _)) => {
+                        return Err(ShaderGenError::ConflictingFunction {
+                            function: function.name.clone(),
+                            paths: [existing_path.clone(), path.map(str::to_string)],
+                        });
+                    }
+                    None => resolved.push((path.map(str::to_string), function.clone())),
+                }
+            }
+            FunctionDefinitionType::ImportFn { path } => {
+                let import = (path.clone(), function.name.clone());
+                if importing.contains(&import) {
+                    let mut cycle: Vec<String> = importing
+                        .iter()
+                        .map(|(path, name)| format!("{}:{}", path, name))
+                        .collect();
+                    cycle.push(format!("{}:{}", path, function.name));
+                    return Err(ShaderGenError::InvalidImport {
+                        function: function.name.clone(),
+                        path: path.clone(),
+                        error: DependencyError::Cycle { functions: cycle },
+                    });
+                }
+                let unresolved = || ShaderGenError::UnresolvedImport {
+                    function: function.name.clone(),
+                    path: path.clone(),
+                };
+                let builtin;
+                let file_functions = match path.strip_prefix(BUILTIN_PREFIX) {
+                    Some(file) => {
+                        builtin = builtin_functions(file).ok_or_else(unresolved)?;
+                        builtin.as_slice()
+                    }
+                    None => library.functions(path).ok_or_else(unresolved)?,
+                };
+                let definitions: Vec<FunctionDefinition> =
+                    resolve_function_dependencies(&[&function.name], file_functions)
+                        .map_err(|error| ShaderGenError::InvalidImport {
+                            function: function.name.clone(),
+                            path: path.clone(),
+                            error,
+                        })?
+                        .into_iter()
+                        .cloned()
+                        .collect();
+                importing.push(import);
+                resolve_functions(&definitions, Some(path), library, importing, resolved)?;
+                importing.pop();
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WebGLDataType;
+
+    fn import_fn(name: &str, path: &str) -> FunctionDefinition {
+        FunctionDefinition {
+            name: name.into(),
+            definition: FunctionDefinitionType::ImportFn { path: path.into() },
+        }
+    }
+
+    fn inline_fn(name: &str, body: &str) -> FunctionDefinition {
+        FunctionDefinition {
+            name: name.into(),
+            definition: FunctionDefinitionType::InlineFn {
+                return_type: WebGLDataType::Float,
+                parameters: "vec3 c".into(),
+                body: body.into(),
+            },
+        }
+    }
+
+    fn names(functions: &[FunctionDefinition]) -> Vec<&str> {
+        functions
+            .iter()
+            .map(|function| function.name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn unknown_path_is_unresolved() {
+        let library = MemoryFunctionLibrary::new();
+        let error =
+            resolve_imported_functions(&[import_fn("luma", "color.yaml")], &library).unwrap_err();
+        assert!(matches!(
+            error,
+            ShaderGenError::UnresolvedImport { function, path }
+                if function == "luma" && path == "color.yaml"
+        ));
+    }
+
+    #[test]
+    fn builtin_imports_pull_in_their_dependencies() {
+        let library = MemoryFunctionLibrary::new();
+        let resolved =
+            resolve_imported_functions(&[import_fn("PBRLighting", "builtin:pbr")], &library)
+                .unwrap();
+        assert_eq!(
+            names(&resolved),
+            [
+                "DistributionGGX",
+                "GeometrySchlickGGX",
+                "GeometrySmith",
+                "fresnelSchlick",
+                "PBRLighting"
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_builtin_is_unresolved() {
+        let library = MemoryFunctionLibrary::new();
+        let error = resolve_imported_functions(&[import_fn("noise", "builtin:nope")], &library)
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            ShaderGenError::UnresolvedImport { function, path }
+                if function == "noise" && path == "builtin:nope"
+        ));
+    }
+
+    #[test]
+    fn files_importing_each_other_are_a_cycle() {
+        let mut library = MemoryFunctionLibrary::new();
+        library.insert("a.toml", vec![import_fn("ping", "b.toml")]);
+        library.insert("b.toml", vec![import_fn("ping", "a.toml")]);
+        let error =
+            resolve_imported_functions(&[import_fn("ping", "a.toml")], &library).unwrap_err();
+        match error {
+            ShaderGenError::InvalidImport {
+                function,
+                error: DependencyError::Cycle { functions },
+                ..
+            } => {
+                assert_eq!(function, "ping");
+                assert_eq!(functions, ["a.toml:ping", "b.toml:ping", "a.toml:ping"]);
+            }
+            error => panic!("expected an import cycle, got {:?}", error),
+        }
+    }
+
+    #[test]
+    fn transitive_imports_are_pulled_in() {
+        let mut library = MemoryFunctionLibrary::new();
+        library
+            .insert_str(
+                "lighting.toml",
+                r#"
+                [[function_definitions]]
+                name = "luma"
+                [function_definitions.definition]
+                type = "ImportFn"
+                path = "color.yaml"
+
+                [[function_definitions]]
+                name = "shade"
+                [function_definitions.definition]
+                type = "InlineFn"
+                return_type = "Float"
+                parameters = "vec3 c"
+                body = "return luma(c) * 0.5;"
+                "#,
+            )
+            .unwrap();
+        library
+            .insert_str(
+                "color.yaml",
+                "function_definitions:\n\
+                 - name: luma\n  \
+                   definition:\n    \
+                     type: InlineFn\n    \
+                     return_type: Float\n    \
+                     parameters: vec3 c\n    \
+                     body: \"return dot(c, vec3(0.2126, 0.7152, 0.0722));\"\n",
+            )
+            .unwrap();
+        assert!(library.contains("color.yaml"));
+
+        let resolved =
+            resolve_imported_functions(&[import_fn("shade", "lighting.toml")], &library).unwrap();
+        assert_eq!(names(&resolved), ["luma", "shade"]);
+        assert!(resolved.iter().all(|function| matches!(
+            function.definition,
+            FunctionDefinitionType::InlineFn { .. }
+        )));
+    }
+
+    #[test]
+    fn function_reached_through_two_files_is_added_once() {
+        let mut library = MemoryFunctionLibrary::new();
+        library.insert("color.toml", vec![inline_fn("luma", "return c.g;")]);
+        library.insert(
+            "tonemap.toml",
+            vec![
+                import_fn("luma", "color.toml"),
+                inline_fn("tonemap", "return luma(c) / (1.0 + luma(c));"),
+            ],
+        );
+        library.insert(
+            "fog.toml",
+            vec![
+                import_fn("luma", "color.toml"),
+                inline_fn("fog", "return luma(c) * 0.1;"),
+            ],
+        );
+        let resolved = resolve_imported_functions(
+            &[
+                import_fn("tonemap", "tonemap.toml"),
+                import_fn("fog", "fog.toml"),
+            ],
+            &library,
+        )
+        .unwrap();
+        assert_eq!(names(&resolved), ["luma", "tonemap", "fog"]);
+    }
+
+    #[test]
+    fn different_functions_with_the_same_name_conflict() {
+        let mut library = MemoryFunctionLibrary::new();
+        library.insert("rec709.toml", vec![inline_fn("luma", "return c.g;")]);
+        library.insert("average.toml", vec![inline_fn("luma", "return c.r;")]);
+        let error = resolve_imported_functions(
+            &[
+                import_fn("luma", "rec709.toml"),
+                import_fn("luma", "average.toml"),
+            ],
+            &library,
+        )
+        .unwrap_err();
+        match error {
+            ShaderGenError::ConflictingFunction { function, paths } => {
+                assert_eq!(function, "luma");
+                assert_eq!(
+                    paths,
+                    [
+                        Some("rec709.toml".to_string()),
+                        Some("average.toml".to_string())
+                    ]
+                );
+            }
+            error => panic!("expected a function conflict, got {:?}", error),
+        }
+
+        let source_luma = inline_fn("luma", "return c.b;");
+        let error =
+            resolve_imported_functions(&[source_luma, import_fn("luma", "rec709.toml")], &library)
+                .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "function 'luma' is defined both in the shader source and in 'rec709.toml'"
+        );
+    }
+}
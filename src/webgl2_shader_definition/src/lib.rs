@@ -1,8 +1,32 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, fmt};
 
 use serde_derive::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+// Used by the code generated with `#[derive(UniformBlock)]`
+pub use bytemuck;
+
+mod dependencies;
+pub use dependencies::*;
+mod diagnostics;
+pub use diagnostics::*;
+mod error;
+pub use error::*;
+mod format;
+pub use format::*;
+mod function_library;
+pub use function_library::*;
+pub mod shader_functions;
+pub use shader_functions::*;
+mod std140;
+pub use std140::*;
+mod uniform_block;
+pub use uniform_block::*;
+mod validation;
+pub use validation::*;
+mod variants;
+pub use variants::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum WebGLDataType {
     Void,
 
@@ -113,9 +137,59 @@ impl WebGLDataType {
             WebGLDataType::USampler2DArray => "usampler2DArray",
         }
     }
+
+    /// Opaque types (samplers) can only be used as `in` function parameters or uniforms
+    pub fn is_opaque(&self) -> bool {
+        matches!(
+            self,
+            WebGLDataType::Sampler2D
+                | WebGLDataType::Sampler3D
+                | WebGLDataType::SamplerCube
+                | WebGLDataType::SamplerCubeShadow
+                | WebGLDataType::Sampler2DShadow
+                | WebGLDataType::Sampler2DArray
+                | WebGLDataType::Sampler2DArrayShadow
+                | WebGLDataType::ISampler2D
+                | WebGLDataType::ISampler3D
+                | WebGLDataType::ISamplerCube
+                | WebGLDataType::ISampler2DArray
+                | WebGLDataType::USampler2D
+                | WebGLDataType::USampler3D
+                | WebGLDataType::USamplerCube
+                | WebGLDataType::USampler2DArray
+        )
+    }
+
+    pub fn is_bool(&self) -> bool {
+        matches!(
+            self,
+            WebGLDataType::Bool | WebGLDataType::BVec2 | WebGLDataType::BVec3 | WebGLDataType::BVec4
+        )
+    }
+
+    pub fn is_integer(&self) -> bool {
+        matches!(
+            self,
+            WebGLDataType::Int
+                | WebGLDataType::IVec2
+                | WebGLDataType::IVec3
+                | WebGLDataType::IVec4
+                | WebGLDataType::Uint
+                | WebGLDataType::UVec2
+                | WebGLDataType::UVec3
+                | WebGLDataType::UVec4
+        )
+    }
+
+    pub fn is_matrix(&self) -> bool {
+        matches!(
+            self,
+            WebGLDataType::Mat2 | WebGLDataType::Mat3 | WebGLDataType::Mat4
+        )
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ShaderAttribute {
     pub layout_loc: u32,
     pub kind: WebGLDataType,
@@ -130,22 +204,69 @@ impl ShaderAttribute {
             name: "frag_color".into(),
         }
     }
+
+    /// A fragment output written to the color attachment `layout_loc` of the framebuffer,
+    /// G-buffers declare one per render target (`vec4 albedo`, `uvec2 material_id`, ...)
+    pub fn frag_output(layout_loc: u32, kind: WebGLDataType, name: &str) -> Self {
+        Self {
+            layout_loc,
+            kind,
+            name: name.into(),
+        }
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ShaderVarying {
     pub interp: Option<InterpolationQualifier>,
     pub kind: WebGLDataType,
     pub name: String,
+    /// Keyword condition, the varying is only declared in variants where it holds
+    pub when: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
 pub enum ParameterQualifier {
     In,
     Out,
     InOut,
 }
 
+impl ParameterQualifier {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ParameterQualifier::In => "in",
+            ParameterQualifier::Out => "out",
+            ParameterQualifier::InOut => "inout",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Precision {
+    Lowp,
+    Mediump,
+    Highp,
+}
+
+impl Precision {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Precision::Lowp => "lowp",
+            Precision::Mediump => "mediump",
+            Precision::Highp => "highp",
+        }
+    }
+}
+
+/// Default precision of a type in a stage: `precision mediump float;`.
+/// Only `Float`, `Int` and the sampler types can take a default precision.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct PrecisionQualifier {
+    pub kind: WebGLDataType,
+    pub precision: Precision,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct FunctionParameter {
     pub qualifier: Option<ParameterQualifier>,
@@ -154,48 +275,152 @@ pub struct FunctionParameter {
     pub array_length: Option<u32>,
 }
 
-impl FunctionParameter {
-    pub fn as_str(&self) -> String {
-        let array_len: Cow<'_, str> = if self.array_length.is_none() {
-            Cow::Borrowed("")
-        } else {
-            Cow::Owned(format!("[{}]", self.array_length.unwrap()))
-        };
-        match self.kind {
-            WebGLDataType::Void => {
-                panic!("Variable cannot be of type void")
+/// Problems found in a structured function parameter before it reaches the GLSL compiler
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParameterError {
+    VoidType {
+        name: String,
+    },
+    ZeroArrayLength {
+        name: String,
+    },
+    OpaqueQualifier {
+        name: String,
+        kind: WebGLDataType,
+        qualifier: ParameterQualifier,
+    },
+    DuplicateName {
+        name: String,
+    },
+}
+
+impl fmt::Display for ParameterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParameterError::VoidType { name } => {
+                write!(f, "parameter '{}' cannot be of type void", name)
             }
-            _ => {
-                let qualifier_str = {
-                    if let Some(qualifier) = &self.qualifier {
-                        match qualifier {
-                            ParameterQualifier::In => "in",
-                            ParameterQualifier::Out => "out",
-                            ParameterQualifier::InOut => "inout",
-                        }
-                    } else {
-                        ""
-                    }
-                };
-                format!(
-                    "{} {} {}{}",
-                    qualifier_str,
-                    self.kind.as_str(),
-                    self.name,
-                    array_len
-                )
+            ParameterError::ZeroArrayLength { name } => {
+                write!(f, "parameter '{}' has an array length of 0", name)
+            }
+            ParameterError::OpaqueQualifier {
+                name,
+                kind,
+                qualifier,
+            } => write!(
+                f,
+                "parameter '{}' of opaque type {} cannot use the '{}' qualifier",
+                name,
+                kind.as_str(),
+                qualifier.as_str()
+            ),
+            ParameterError::DuplicateName { name } => {
+                write!(f, "parameter '{}' is declared more than once", name)
+            }
+        }
+    }
+}
+
+impl FunctionParameter {
+    /// Checks the rules GLSL ES 3.00 applies to a single parameter declaration
+    pub fn validate(&self) -> Result<(), ParameterError> {
+        if let WebGLDataType::Void = self.kind {
+            return Err(ParameterError::VoidType {
+                name: self.name.clone(),
+            });
+        }
+        if self.array_length == Some(0) {
+            return Err(ParameterError::ZeroArrayLength {
+                name: self.name.clone(),
+            });
+        }
+        match self.qualifier {
+            Some(qualifier @ (ParameterQualifier::Out | ParameterQualifier::InOut))
+                if self.kind.is_opaque() =>
+            {
+                Err(ParameterError::OpaqueQualifier {
+                    name: self.name.clone(),
+                    kind: self.kind,
+                    qualifier,
+                })
             }
+            _ => Ok(()),
         }
     }
+
+    pub fn as_str(&self) -> Result<String, ParameterError> {
+        self.validate()?;
+        let array_len: Cow<'_, str> = match self.array_length {
+            Some(length) => Cow::Owned(format!("[{}]", length)),
+            None => Cow::Borrowed(""),
+        };
+        let qualifier_str: Cow<'_, str> = match &self.qualifier {
+            Some(qualifier) => Cow::Owned(format!("{} ", qualifier.as_str())),
+            None => Cow::Borrowed(""),
+        };
+        Ok(format!(
+            "{}{} {}{}",
+            qualifier_str,
+            self.kind.as_str(),
+            self.name,
+            array_len
+        ))
+    }
 }
 
-#[allow(dead_code)]
-fn create_fn_param_str(params: &Vec<FunctionParameter>) -> String {
-    params
+fn create_fn_param_str(params: &[FunctionParameter]) -> Result<String, ParameterError> {
+    Ok(params
         .iter()
         .map(|param| param.as_str())
-        .collect::<Vec<String>>()
-        .join(" , ")
+        .collect::<Result<Vec<String>, ParameterError>>()?
+        .join(", "))
+}
+
+/// Parameter list of an inline function. It can be written as the raw GLSL parameter string
+/// (`parameters = "vec2 uv, float t"`) or as a list of typed parameters that the generator checks
+/// (`parameters = [{ kind = "Vec2", name = "uv" }, { kind = "Float", name = "t" }]`)
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum FunctionParameters {
+    Raw(String),
+    List(Vec<FunctionParameter>),
+}
+
+impl FunctionParameters {
+    /// Validates the parameters (only possible for the `List` form) and returns the GLSL parameter string
+    pub fn to_glsl(&self) -> Result<String, ParameterError> {
+        match self {
+            FunctionParameters::Raw(parameters) => Ok(parameters.clone()),
+            FunctionParameters::List(parameters) => {
+                for (index, param) in parameters.iter().enumerate() {
+                    if parameters[..index].iter().any(|other| other.name == param.name) {
+                        return Err(ParameterError::DuplicateName {
+                            name: param.name.clone(),
+                        });
+                    }
+                }
+                create_fn_param_str(parameters)
+            }
+        }
+    }
+}
+
+impl From<&str> for FunctionParameters {
+    fn from(parameters: &str) -> Self {
+        FunctionParameters::Raw(parameters.to_string())
+    }
+}
+
+impl From<String> for FunctionParameters {
+    fn from(parameters: String) -> Self {
+        FunctionParameters::Raw(parameters)
+    }
+}
+
+impl From<Vec<FunctionParameter>> for FunctionParameters {
+    fn from(parameters: Vec<FunctionParameter>) -> Self {
+        FunctionParameters::List(parameters)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -203,7 +428,7 @@ fn create_fn_param_str(params: &Vec<FunctionParameter>) -> String {
 pub enum FunctionDefinitionType {
     InlineFn {
         return_type: WebGLDataType,
-        parameters: String, //Vec<FunctionParameter>,
+        parameters: FunctionParameters,
         body: String,
     },
     ImportFn {
@@ -217,11 +442,57 @@ pub struct FunctionDefinition {
     pub definition: FunctionDefinitionType,
 }
 
+/// Type of a uniform, either a built-in type or the name of a struct declared in
+/// `ShaderSource::structs` (`kind = "Vec3"` or `kind = "Light"`)
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum UniformKind {
+    Data(WebGLDataType),
+    Struct(String),
+}
+
+impl UniformKind {
+    pub fn as_str(&self) -> &str {
+        match self {
+            UniformKind::Data(kind) => kind.as_str(),
+            UniformKind::Struct(name) => name,
+        }
+    }
+
+    pub fn data_type(&self) -> Option<WebGLDataType> {
+        match self {
+            UniformKind::Data(kind) => Some(*kind),
+            UniformKind::Struct(_) => None,
+        }
+    }
+
+    pub fn struct_name(&self) -> Option<&str> {
+        match self {
+            UniformKind::Data(_) => None,
+            UniformKind::Struct(name) => Some(name),
+        }
+    }
+}
+
+impl From<WebGLDataType> for UniformKind {
+    fn from(kind: WebGLDataType) -> Self {
+        UniformKind::Data(kind)
+    }
+}
+
+impl From<&str> for UniformKind {
+    fn from(name: &str) -> Self {
+        UniformKind::Struct(name.to_string())
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ShaderUniform {
     pub array_length: Option<u32>,
-    pub kind: WebGLDataType,
+    pub kind: UniformKind,
     pub name: String,
+    /// Keyword condition, the uniform is only declared in variants where it holds
+    pub when: Option<String>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -229,9 +500,19 @@ pub struct ShaderUniformBlock {
     pub binding_number: u32,
     pub name: String,
     pub uniforms: Vec<ShaderUniform>,
+    /// Keyword condition, the block is only declared in variants where it holds
+    pub when: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// A GLSL struct that uniforms and uniform block members can use as their `kind`.
+/// Members can be arrays and other structs, a struct cannot contain itself.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShaderStruct {
+    pub name: String,
+    pub members: Vec<ShaderUniform>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UniformCollection {
     pub uniforms: Vec<ShaderUniform>,
     pub uniform_blocks: Vec<ShaderUniformBlock>,
@@ -246,30 +527,108 @@ impl UniformCollection {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Entry of a stage `import_fn` list, either the name of the function or a function that is only
+/// imported when the keyword condition holds (`{ name = "apply_skinning", when = "SKINNED" }`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum FunctionImport {
+    Name(String),
+    Conditional { name: String, when: String },
+}
+
+impl FunctionImport {
+    pub fn name(&self) -> &str {
+        match self {
+            FunctionImport::Name(name) | FunctionImport::Conditional { name, .. } => name,
+        }
+    }
+
+    pub fn condition(&self) -> Option<String> {
+        match self {
+            FunctionImport::Name(_) => None,
+            FunctionImport::Conditional { when, .. } => Some(when.clone()),
+        }
+    }
+}
+
+impl From<&str> for FunctionImport {
+    fn from(name: &str) -> Self {
+        FunctionImport::Name(name.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ShaderStage {
-    pub import_fn: Vec<String>,
+    /// Version of the `#version` directive, `300 es` when it is not set
+    pub version: Option<String>,
+    /// Default precisions of the stage, they replace the `highp` defaults for float and int
+    #[serde(default)]
+    pub precision: Vec<PrecisionQualifier>,
+    /// Lines placed right after the `#version` directive, like `#extension` directives
+    #[serde(default)]
+    pub header: Vec<String>,
+    pub import_fn: Vec<FunctionImport>,
     pub main_fn: String,
     pub attributes: Vec<ShaderAttribute>,
     pub uniform_collection: UniformCollection,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TransformFeedbackMode {
+    /// Every captured varying is written to a single buffer
+    Interleaved,
+    /// Each captured varying is written to its own buffer, in declaration order
+    Separate,
+}
+
+/// Vertex stage outputs captured into transform feedback buffers. The names can be varyings of
+/// the source or `gl_Position` / `gl_PointSize`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransformFeedback {
+    pub mode: TransformFeedbackMode,
+    pub varyings: Vec<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct FunctionImports {
     pub function_definitions: Vec<FunctionDefinition>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ShaderSource {
     pub name: String,
+    #[serde(default)]
+    pub keywords: Vec<ShaderKeyword>,
+    #[serde(default)]
+    pub structs: Vec<ShaderStruct>,
     pub varyings: Vec<ShaderVarying>,
     pub common_uniforms: UniformCollection,
     pub imported_functions: Vec<FunctionDefinition>,
     pub local_import: Option<String>,
     pub vertex_shader: ShaderStage,
-    pub fragment_shader: ShaderStage,
+    /// Without a fragment stage an empty fragment shader is generated, for programs that only
+    /// run the vertex stage to capture its outputs with transform feedback. This field used to be
+    /// a `ShaderStage`: definition files do not change, Rust code building a `ShaderSource` wraps
+    /// its fragment stage in `Some`.
+    #[serde(default)]
+    pub fragment_shader: Option<ShaderStage>,
+    #[serde(default)]
+    pub transform_feedback: Option<TransformFeedback>,
+}
+
+impl ShaderSource {
+    /// Fragment stage outputs sorted by location
+    pub fn fragment_outputs(&self) -> Vec<&ShaderAttribute> {
+        let mut outputs: Vec<&ShaderAttribute> = match &self.fragment_shader {
+            Some(fragment_shader) => fragment_shader.attributes.iter().collect(),
+            None => Vec::new(),
+        };
+        outputs.sort_by_key(|output| output.layout_loc);
+        outputs
+    }
 }
 
+/// Parses a TOML shader source, `load_shader_source` reads the other formats
 pub fn shader_source_from_str(shader: &str) -> Result<ShaderSource, toml::de::Error> {
     match toml::from_str::<ShaderSource>(shader) {
         Ok(source) => Ok(source),
@@ -278,84 +637,137 @@ pub fn shader_source_from_str(shader: &str) -> Result<ShaderSource, toml::de::Er
 }
 
 fn push_imported_function(
-    shader_code: &mut String,
-    import_fn: &Vec<String>,
-    imported_functions: &Vec<FunctionDefinition>,
-) {
-    for imported_fn in import_fn {
-        match imported_functions
-            .iter()
-            .find(|fn_def| fn_def.name == *imported_fn)
+    shader_code: &mut ShaderWriter,
+    import_fn: &[FunctionImport],
+    imported_functions: &[FunctionDefinition],
+    stage: ShaderStageKind,
+) -> Result<(), ShaderGenError> {
+    let import_names: Vec<&str> = import_fn.iter().map(|import| import.name()).collect();
+    let functions = resolve_function_dependencies(&import_names, imported_functions)
+        .map_err(|err| ShaderGenError::from_dependency_error(err, stage))?;
+    for imp_fn in functions {
+        if let FunctionDefinitionType::InlineFn {
+            return_type,
+            parameters,
+            body,
+        } = &imp_fn.definition
         {
-            Some(imp_fn) => {
-                if let FunctionDefinitionType::InlineFn {
-                    return_type,
-                    parameters,
-                    body,
-                } = &imp_fn.definition
-                {
-                    let mut imported_function_str = format!(
-                        "{} {} ( {} ) {{\n",
-                        return_type.as_str(),
-                        imp_fn.name,
-                        parameters
-                    );
-                    imported_function_str.extend(body.chars());
-                    imported_function_str.push('\n');
-                    imported_function_str.push('}');
-                    imported_function_str.push('\n');
-
-                    shader_code.extend(imported_function_str.chars());
-                }
+            let parameters =
+                parameters
+                    .to_glsl()
+                    .map_err(|error| ShaderGenError::InvalidParameter {
+                        function: imp_fn.name.clone(),
+                        stage,
+                        error,
+                    })?;
+            let section = ShaderSection::ImportedFunction(imp_fn.name.clone());
+            let signature = format!(
+                "{} {} ( {} ) {{\n",
+                return_type.as_str(),
+                imp_fn.name,
+                parameters
+            );
+            shader_code.push(section.clone(), &signature);
+            shader_code.push_authored(section.clone(), body);
+            shader_code.push(section, "\n}\n");
+        }
+    }
+    Ok(())
+}
+
+fn uniform_declaration(uniform: &ShaderUniform) -> String {
+    match uniform.array_length {
+        Some(len) => format!("{} {}[{}]", uniform.kind.as_str(), uniform.name, len),
+        None => format!("{} {}", uniform.kind.as_str(), uniform.name),
+    }
+}
+
+fn visit_struct<'a>(name: &str, structs: &'a [ShaderStruct], used: &mut Vec<&'a ShaderStruct>) {
+    if used.iter().any(|used_struct| used_struct.name == name) {
+        return;
+    }
+    if let Some(shader_struct) = structs.iter().find(|s| s.name == name) {
+        // Pushed before visiting the members so a recursive struct cannot loop forever,
+        // validation reports those. The members are moved ahead of it afterwards.
+        let position = used.len();
+        used.push(shader_struct);
+        for member in &shader_struct.members {
+            if let Some(member_struct) = member.kind.struct_name() {
+                visit_struct(member_struct, structs, used);
             }
-            None => panic!("Function not found"),
         }
+        let shader_struct = used.remove(position);
+        used.push(shader_struct);
+    }
+}
+
+/// Structs used by the uniforms of `collections`, each struct comes after the structs it contains
+fn used_structs<'a>(
+    structs: &'a [ShaderStruct],
+    collections: &[&UniformCollection],
+) -> Vec<&'a ShaderStruct> {
+    let mut used = Vec::new();
+    for collection in collections {
+        let block_uniforms = collection
+            .uniform_blocks
+            .iter()
+            .flat_map(|block| block.uniforms.iter());
+        for uniform in collection.uniforms.iter().chain(block_uniforms) {
+            if let Some(name) = uniform.kind.struct_name() {
+                visit_struct(name, structs, &mut used);
+            }
+        }
+    }
+    used
+}
+
+fn push_structs(
+    shader_code: &mut ShaderWriter,
+    structs: &[ShaderStruct],
+    collections: &[&UniformCollection],
+) {
+    for shader_struct in used_structs(structs, collections) {
+        let mut struct_str = format!("struct {} {{\n", shader_struct.name);
+        for member in &shader_struct.members {
+            struct_str.push_str(&format!(" {};\n", uniform_declaration(member)));
+        }
+        struct_str.push_str("};\n");
+        shader_code.push(
+            ShaderSection::Struct(shader_struct.name.clone()),
+            &struct_str,
+        );
     }
 }
 
-fn push_uniform_collection(shader_code: &mut String, u_collection: &UniformCollection) {
+fn push_uniform_collection(shader_code: &mut ShaderWriter, u_collection: &UniformCollection) {
     for uniform_block in &u_collection.uniform_blocks {
         let mut uniform_block_str = format!("layout (std140) uniform {} {{\n", uniform_block.name);
         for uniform in &uniform_block.uniforms {
-            let uniform_line = format!(" {} {};\n", uniform.kind.as_str(), uniform.name);
-            uniform_block_str.extend(uniform_line.chars());
+            uniform_block_str.push_str(&format!(" {};\n", uniform_declaration(uniform)));
         }
         uniform_block_str.push('}');
         uniform_block_str.push(';');
         uniform_block_str.push('\n');
-        shader_code.extend(uniform_block_str.chars());
+        shader_code.push(
+            ShaderSection::UniformBlock(uniform_block.name.clone()),
+            &uniform_block_str,
+        );
     }
 
     for uniform in &u_collection.uniforms {
-        let uniform_line = match uniform.array_length {
-            Some(len) => {
-                format!(
-                    "uniform {} {}[{}];\n",
-                    uniform.kind.as_str(),
-                    uniform.name,
-                    len
-                )
-            }
-            None => {
-                format!("uniform {} {};\n", uniform.kind.as_str(), uniform.name)
-            }
-        };
-
-        shader_code.extend(uniform_line.chars());
+        let uniform_line = format!("uniform {};\n", uniform_declaration(uniform));
+        shader_code.push(ShaderSection::Uniform(uniform.name.clone()), &uniform_line);
     }
 }
 
-fn push_main_function(shader_code: &mut String, main_fn: &String) {
-    let mut main_function = format!("void main() {{\n");
-    main_function.extend(main_fn.chars());
-    main_function.push('\n');
-    main_function.push('}');
-
-    shader_code.extend(main_function.chars());
+fn push_main_function(shader_code: &mut ShaderWriter, main_fn: &str) {
+    shader_code.push(ShaderSection::MainFn, "void main() {\n");
+    shader_code.push_authored(ShaderSection::MainFn, main_fn);
+    shader_code.push(ShaderSection::MainFn, "\n}");
 }
 
 // Adds varyings to the shaders code (string)
-fn push_varying(shader_code: &mut String, is_input: bool, varyings: &Vec<ShaderVarying>) {
+fn push_varying(shader_code: &mut ShaderWriter, is_input: bool, varyings: &Vec<ShaderVarying>) {
     for vary in varyings {
         match vary.interp {
             Some(value) => {
@@ -366,7 +778,7 @@ fn push_varying(shader_code: &mut String, is_input: bool, varyings: &Vec<ShaderV
                     vary.kind.as_str(),
                     vary.name
                 );
-                shader_code.extend(vary_str.chars());
+                shader_code.push(ShaderSection::Varying(vary.name.clone()), &vary_str);
             }
             None => {
                 let vary_str = format!(
@@ -375,14 +787,14 @@ fn push_varying(shader_code: &mut String, is_input: bool, varyings: &Vec<ShaderV
                     vary.kind.as_str(),
                     vary.name
                 );
-                shader_code.extend(vary_str.chars());
+                shader_code.push(ShaderSection::Varying(vary.name.clone()), &vary_str);
             }
         }
     }
 }
 
 fn push_stage_attributes(
-    shader_code: &mut String,
+    shader_code: &mut ShaderWriter,
     attributes: &Vec<ShaderAttribute>,
     is_input: bool,
 ) {
@@ -394,50 +806,123 @@ fn push_stage_attributes(
             attr.kind.as_str(),
             attr.name
         );
-        shader_code.extend(attr_line.as_str().chars());
+        shader_code.push(ShaderSection::Attribute(attr.name.clone()), &attr_line);
     }
 }
 
-fn push_local_import(shader_code: &mut String, local_import: &Option<String>) {
+fn push_local_import(shader_code: &mut ShaderWriter, local_import: &Option<String>) {
     if let Some(inline_import) = local_import {
-        let import = format!("\n{}\n", inline_import);
-        shader_code.extend(import.chars());
+        shader_code.push(ShaderSection::LocalImport, "\n");
+        shader_code.push_authored(ShaderSection::LocalImport, inline_import);
+        shader_code.push(ShaderSection::LocalImport, "\n");
     }
 }
 
-pub fn generate_fragment_stage_str(
+// `#version`, the header lines, the default precisions and the keyword defines, in that order
+fn push_header(shader_code: &mut ShaderWriter, stage: &ShaderStage, variant: &ShaderVariant) {
+    let version = stage.version.as_deref().unwrap_or("300 es");
+    let mut header = format!("#version {}\n", version);
+    for line in &stage.header {
+        header.push_str(line);
+        header.push('\n');
+    }
+
+    let mut precisions = vec![
+        PrecisionQualifier {
+            kind: WebGLDataType::Float,
+            precision: Precision::Highp,
+        },
+        PrecisionQualifier {
+            kind: WebGLDataType::Int,
+            precision: Precision::Highp,
+        },
+    ];
+    for qualifier in &stage.precision {
+        match precisions.iter_mut().find(|p| p.kind == qualifier.kind) {
+            Some(precision) => precision.precision = qualifier.precision,
+            None => precisions.push(*qualifier),
+        }
+    }
+    for qualifier in precisions {
+        header.push_str(&format!(
+            "precision {} {};\n",
+            qualifier.precision.as_str(),
+            qualifier.kind.as_str()
+        ));
+    }
+    header.push_str(&variant.defines());
+    shader_code.push(ShaderSection::Header, &header);
+}
+
+// Fragment shader that writes nothing, paired with vertex stages that run without rasterization
+fn empty_fragment_stage_code(vertex_stage: &ShaderStage) -> GeneratedShader {
+    let version = vertex_stage.version.as_deref().unwrap_or("300 es");
+    let mut shader_code = ShaderWriter::new();
+    shader_code.push(
+        ShaderSection::Header,
+        &format!("#version {}\nprecision highp float;\n", version),
+    );
+    shader_code.push(ShaderSection::MainFn, "void main() {}\n");
+    shader_code.finish()
+}
+
+// Generates the fragment stage of a source that was already specialized for `variant`
+fn fragment_stage_code(
     shader_src: &ShaderSource,
-    imported_functions: &Vec<FunctionDefinition>,
-) -> String {
-    let mut shader_code =
-        String::from("#version 300 es\nprecision highp float;\nprecision highp int;\n");
-    push_stage_attributes(&mut shader_code, &shader_src.fragment_shader.attributes, false);
+    imported_functions: &[FunctionDefinition],
+    variant: &ShaderVariant,
+) -> Result<GeneratedShader, ShaderGenError> {
+    let fragment_shader = match &shader_src.fragment_shader {
+        Some(fragment_shader) => fragment_shader,
+        None => return Ok(empty_fragment_stage_code(&shader_src.vertex_shader)),
+    };
+    let mut shader_code = ShaderWriter::new();
+    push_header(&mut shader_code, fragment_shader, variant);
+    push_stage_attributes(&mut shader_code, &fragment_shader.attributes, false);
     push_varying(&mut shader_code, true, &shader_src.varyings);
+    push_structs(
+        &mut shader_code,
+        &shader_src.structs,
+        &[
+            &shader_src.common_uniforms,
+            &fragment_shader.uniform_collection,
+        ],
+    );
     push_uniform_collection(&mut shader_code, &shader_src.common_uniforms);
     push_uniform_collection(
         &mut shader_code,
-        &shader_src.fragment_shader.uniform_collection,
+        &fragment_shader.uniform_collection,
     );
     push_imported_function(
         &mut shader_code,
-        &shader_src.fragment_shader.import_fn,
+        &fragment_shader.import_fn,
         imported_functions,
-    );
+        ShaderStageKind::Fragment,
+    )?;
     push_local_import(&mut shader_code, &shader_src.local_import);
-    push_main_function(&mut shader_code, &shader_src.fragment_shader.main_fn);
+    push_main_function(&mut shader_code, &fragment_shader.main_fn);
 
-    shader_code
+    Ok(shader_code.finish())
 }
 
-pub fn generate_vertex_stage_str(
+// Generates the vertex stage of a source that was already specialized for `variant`
+fn vertex_stage_code(
     shader_src: &ShaderSource,
-    imported_functions: &Vec<FunctionDefinition>,
-) -> String {
-    let mut shader_code =
-        String::from("#version 300 es\nprecision highp float;\nprecision highp int;\n");
-
+    imported_functions: &[FunctionDefinition],
+    variant: &ShaderVariant,
+) -> Result<GeneratedShader, ShaderGenError> {
+    let mut shader_code = ShaderWriter::new();
+    push_header(&mut shader_code, &shader_src.vertex_shader, variant);
     push_stage_attributes(&mut shader_code, &shader_src.vertex_shader.attributes, true);
     push_varying(&mut shader_code, false, &shader_src.varyings);
+    push_structs(
+        &mut shader_code,
+        &shader_src.structs,
+        &[
+            &shader_src.common_uniforms,
+            &shader_src.vertex_shader.uniform_collection,
+        ],
+    );
     push_uniform_collection(&mut shader_code, &shader_src.common_uniforms);
     push_uniform_collection(
         &mut shader_code,
@@ -447,35 +932,456 @@ pub fn generate_vertex_stage_str(
         &mut shader_code,
         &shader_src.vertex_shader.import_fn,
         imported_functions,
-    );
+        ShaderStageKind::Vertex,
+    )?;
     push_local_import(&mut shader_code, &shader_src.local_import);
     push_main_function(&mut shader_code, &shader_src.vertex_shader.main_fn);
 
-    shader_code
+    Ok(shader_code.finish())
+}
+
+fn specialize_source(
+    shader_src: &ShaderSource,
+    selection: &ShaderVariant,
+) -> Result<(ShaderSource, ShaderVariant), ShaderGenError> {
+    let variant = shader_src
+        .resolve_variant(selection)
+        .map_err(ShaderGenError::InvalidVariant)?;
+    let specialized = shader_src
+        .specialize(&variant)
+        .map_err(ShaderGenError::InvalidVariant)?;
+    Ok((specialized, variant))
+}
+
+pub fn generate_fragment_stage_variant_str(
+    shader_src: &ShaderSource,
+    imported_functions: &[FunctionDefinition],
+    variant: &ShaderVariant,
+) -> Result<String, ShaderGenError> {
+    let (specialized, variant) = specialize_source(shader_src, variant)?;
+    fragment_stage_code(&specialized, imported_functions, &variant).map(|shader| shader.code)
+}
+
+pub fn generate_vertex_stage_variant_str(
+    shader_src: &ShaderSource,
+    imported_functions: &[FunctionDefinition],
+    variant: &ShaderVariant,
+) -> Result<String, ShaderGenError> {
+    let (specialized, variant) = specialize_source(shader_src, variant)?;
+    vertex_stage_code(&specialized, imported_functions, &variant).map(|shader| shader.code)
+}
+
+pub fn generate_fragment_stage_str(
+    shader_src: &ShaderSource,
+    imported_functions: &[FunctionDefinition],
+) -> Result<String, ShaderGenError> {
+    generate_fragment_stage_variant_str(shader_src, imported_functions, &ShaderVariant::new())
+}
+
+pub fn generate_vertex_stage_str(
+    shader_src: &ShaderSource,
+    imported_functions: &[FunctionDefinition],
+) -> Result<String, ShaderGenError> {
+    generate_vertex_stage_variant_str(shader_src, imported_functions, &ShaderVariant::new())
+}
+
+// Without a library every `ImportFn` is reported as unresolved
+fn inline_imported_functions(
+    source: &ShaderSource,
+) -> Result<Vec<FunctionDefinition>, ShaderGenError> {
+    resolve_imported_functions(&source.imported_functions, &MemoryFunctionLibrary::new())
 }
 
 pub fn generate_shader_str_from_single_source(
     source: &ShaderSource,
-) -> Result<(String, String), ()> {
-    let imported_fn = {
-        let mut imported_fn_vec = Vec::new();
-        for i_fn in &source.imported_functions {
-            match i_fn.definition {
-                FunctionDefinitionType::InlineFn { .. } => imported_fn_vec.push(i_fn.clone()),
-                FunctionDefinitionType::ImportFn { .. } => return Err(()),
-            }
-        }
-        imported_fn_vec
-    };
-    Ok(generate_vertex_fragment_shader(source, &imported_fn))
+) -> Result<(String, String), ShaderGenError> {
+    let imported_fn = inline_imported_functions(source)?;
+    generate_vertex_fragment_shader(source, &imported_fn)
+}
+
+pub fn generate_shader_variant_str_from_single_source(
+    source: &ShaderSource,
+    variant: &ShaderVariant,
+) -> Result<(String, String), ShaderGenError> {
+    let imported_fn = inline_imported_functions(source)?;
+    generate_vertex_fragment_shader_variant(source, &imported_fn, variant)
+}
+
+/// Same as `generate_shader_variant_str_from_single_source`, each stage comes with the line map
+/// used to report compile errors in terms of the `ShaderSource`
+pub fn generate_shader_variant_from_single_source(
+    source: &ShaderSource,
+    variant: &ShaderVariant,
+) -> Result<(GeneratedShader, GeneratedShader), ShaderGenError> {
+    let imported_fn = inline_imported_functions(source)?;
+    generate_vertex_fragment_shader_variant_mapped(source, &imported_fn, variant)
+}
+
+/// Same as `generate_shader_str_from_single_source`, the `ImportFn` definitions are resolved
+/// with `library`
+pub fn generate_shader_str_with_library(
+    source: &ShaderSource,
+    library: &dyn FunctionLibrary,
+) -> Result<(String, String), ShaderGenError> {
+    let imported_fn = resolve_imported_functions(&source.imported_functions, library)?;
+    generate_vertex_fragment_shader(source, &imported_fn)
+}
+
+/// Same as `generate_shader_variant_from_single_source`, the `ImportFn` definitions are resolved
+/// with `library`
+pub fn generate_shader_variant_with_library(
+    source: &ShaderSource,
+    variant: &ShaderVariant,
+    library: &dyn FunctionLibrary,
+) -> Result<(GeneratedShader, GeneratedShader), ShaderGenError> {
+    let imported_fn = resolve_imported_functions(&source.imported_functions, library)?;
+    generate_vertex_fragment_shader_variant_mapped(source, &imported_fn, variant)
 }
 
 pub fn generate_vertex_fragment_shader(
     source: &ShaderSource,
-    imported_fn: &Vec<FunctionDefinition>,
-) -> (String, String) {
-    let vertex_shader_code = generate_vertex_stage_str(&source, &imported_fn);
-    let fragment_shader_code = generate_fragment_stage_str(&source, &imported_fn);
+    imported_fn: &[FunctionDefinition],
+) -> Result<(String, String), ShaderGenError> {
+    generate_vertex_fragment_shader_variant(source, imported_fn, &ShaderVariant::new())
+}
+
+/// Generates both stages for the keyword values in `variant`, keywords it does not set take their
+/// default value. The specialized source is validated before generating any code.
+pub fn generate_vertex_fragment_shader_variant(
+    source: &ShaderSource,
+    imported_fn: &[FunctionDefinition],
+    variant: &ShaderVariant,
+) -> Result<(String, String), ShaderGenError> {
+    let (vertex_shader, fragment_shader) =
+        generate_vertex_fragment_shader_variant_mapped(source, imported_fn, variant)?;
+    Ok((vertex_shader.code, fragment_shader.code))
+}
 
-    (vertex_shader_code, fragment_shader_code)
+/// Same as `generate_vertex_fragment_shader_variant`, keeping the line map of each stage
+pub fn generate_vertex_fragment_shader_variant_mapped(
+    source: &ShaderSource,
+    imported_fn: &[FunctionDefinition],
+    variant: &ShaderVariant,
+) -> Result<(GeneratedShader, GeneratedShader), ShaderGenError> {
+    let (specialized, variant) = specialize_source(source, variant)?;
+    source.validate_keywords().map_err(ShaderGenError::InvalidSource)?;
+    specialized
+        .validate_interface()
+        .map_err(ShaderGenError::InvalidSource)?;
+    let vertex_shader_code = vertex_stage_code(&specialized, imported_fn, &variant)?;
+    let fragment_shader_code = fragment_stage_code(&specialized, imported_fn, &variant)?;
+
+    Ok((vertex_shader_code, fragment_shader_code))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inline_parameters(definition: &FunctionDefinition) -> &FunctionParameters {
+        match &definition.definition {
+            FunctionDefinitionType::InlineFn { parameters, .. } => parameters,
+            FunctionDefinitionType::ImportFn { .. } => panic!("expected an inline function"),
+        }
+    }
+
+    #[test]
+    fn typed_parameter_list_from_toml() {
+        let imports: FunctionImports = toml::from_str(
+            r#"
+            [[function_definitions]]
+            name = "blend"
+            [function_definitions.definition]
+            type = "InlineFn"
+            return_type = "Vec3"
+            parameters = [
+                { kind = "Vec3", name = "a" },
+                { qualifier = "Out", kind = "Float", name = "weights", array_length = 4 },
+            ]
+            body = "return a;"
+            "#,
+        )
+        .unwrap();
+        let parameters = inline_parameters(&imports.function_definitions[0]);
+        assert!(matches!(parameters, FunctionParameters::List(list) if list.len() == 2));
+        assert_eq!(
+            parameters.to_glsl().unwrap(),
+            "vec3 a, out float weights[4]"
+        );
+    }
+
+    #[test]
+    fn typed_parameter_list_from_yaml() {
+        let imports: FunctionImports = serde_yaml::from_str(
+            r#"
+function_definitions:
+  - name: blend
+    definition:
+      type: InlineFn
+      return_type: Vec3
+      parameters:
+        - { qualifier: In, kind: Vec3, name: a }
+        - { kind: Sampler2D, name: tex }
+      body: return a;
+"#,
+        )
+        .unwrap();
+        let parameters = inline_parameters(&imports.function_definitions[0]);
+        assert!(matches!(parameters, FunctionParameters::List(list) if list.len() == 2));
+        assert_eq!(parameters.to_glsl().unwrap(), "in vec3 a, sampler2D tex");
+    }
+
+    #[test]
+    fn raw_parameter_string_still_deserializes() {
+        let imports: FunctionImports = toml::from_str(
+            r#"
+            [[function_definitions]]
+            name = "blend"
+            [function_definitions.definition]
+            type = "InlineFn"
+            return_type = "Vec3"
+            parameters = "vec3 a, vec3 b"
+            body = "return a + b;"
+            "#,
+        )
+        .unwrap();
+        let parameters = inline_parameters(&imports.function_definitions[0]);
+        assert!(matches!(parameters, FunctionParameters::Raw(raw) if raw == "vec3 a, vec3 b"));
+        assert_eq!(parameters.to_glsl().unwrap(), "vec3 a, vec3 b");
+
+        let imports: FunctionImports = serde_yaml::from_str(
+            r#"
+function_definitions:
+  - name: blend
+    definition:
+      type: InlineFn
+      return_type: Vec3
+      parameters: vec3 a, vec3 b
+      body: return a + b;
+"#,
+        )
+        .unwrap();
+        let parameters = inline_parameters(&imports.function_definitions[0]);
+        assert!(matches!(parameters, FunctionParameters::Raw(raw) if raw == "vec3 a, vec3 b"));
+    }
+
+    #[test]
+    fn void_parameter_is_rejected() {
+        let parameters = FunctionParameters::from(vec![FunctionParameter {
+            qualifier: None,
+            kind: WebGLDataType::Void,
+            name: "nothing".into(),
+            array_length: None,
+        }]);
+        assert_eq!(
+            parameters.to_glsl(),
+            Err(ParameterError::VoidType {
+                name: "nothing".into()
+            })
+        );
+    }
+
+    #[test]
+    fn out_parameter_with_zero_array_length_is_rejected() {
+        let parameters = FunctionParameters::from(vec![FunctionParameter {
+            qualifier: Some(ParameterQualifier::Out),
+            kind: WebGLDataType::Vec4,
+            name: "colors".into(),
+            array_length: Some(0),
+        }]);
+        assert_eq!(
+            parameters.to_glsl(),
+            Err(ParameterError::ZeroArrayLength {
+                name: "colors".into()
+            })
+        );
+    }
+
+    #[test]
+    fn opaque_out_parameter_and_duplicate_names_are_rejected() {
+        let sampler = FunctionParameter {
+            qualifier: Some(ParameterQualifier::InOut),
+            kind: WebGLDataType::Sampler2D,
+            name: "tex".into(),
+            array_length: None,
+        };
+        assert_eq!(
+            sampler.validate(),
+            Err(ParameterError::OpaqueQualifier {
+                name: "tex".into(),
+                kind: WebGLDataType::Sampler2D,
+                qualifier: ParameterQualifier::InOut,
+            })
+        );
+
+        let uv = FunctionParameter {
+            qualifier: None,
+            kind: WebGLDataType::Vec2,
+            name: "uv".into(),
+            array_length: None,
+        };
+        let parameters = FunctionParameters::from(vec![uv.clone(), uv]);
+        assert_eq!(
+            parameters.to_glsl(),
+            Err(ParameterError::DuplicateName { name: "uv".into() })
+        );
+    }
+
+    fn header_source() -> ShaderSource {
+        shader_source_from_str(
+            r##"
+            name = "header"
+            keywords = [
+                { type = "Bool", name = "SKINNED" },
+                { type = "Int", name = "LIGHTS", values = [1, 4], default = 1 },
+            ]
+            varyings = []
+            imported_functions = []
+
+            [[structs]]
+            name = "Scene"
+            members = [
+                { kind = "Light", name = "lights", array_length = 2 },
+                { kind = "Fog", name = "fog" },
+            ]
+            [[structs]]
+            name = "Unused"
+            members = [{ kind = "Float", name = "value" }]
+            [[structs]]
+            name = "Light"
+            members = [
+                { kind = "Vec3", name = "color" },
+                { kind = "Shadow", name = "shadow" },
+            ]
+            [[structs]]
+            name = "Fog"
+            members = [{ kind = "Float", name = "density" }]
+            [[structs]]
+            name = "Shadow"
+            members = [{ kind = "Mat4", name = "light_space" }]
+
+            [common_uniforms]
+            uniforms = []
+            uniform_blocks = [
+                { binding_number = 0, name = "SceneBlock", uniforms = [{ kind = "Scene", name = "scene" }] },
+            ]
+
+            [vertex_shader]
+            version = "300 es"
+            header = ["#extension GL_OVR_multiview2 : require", "layout(num_views = 2) in;"]
+            precision = [
+                { kind = "Float", precision = "Mediump" },
+                { kind = "Sampler2DShadow", precision = "Lowp" },
+            ]
+            import_fn = []
+            main_fn = ""
+            attributes = []
+            [vertex_shader.uniform_collection]
+            uniforms = []
+            uniform_blocks = []
+            "##,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn header_lines_come_in_order() {
+        let mut variant = ShaderVariant::new();
+        variant.set_bool("SKINNED", true);
+        variant.set_int("LIGHTS", 4);
+        let vertex = generate_vertex_stage_variant_str(&header_source(), &[], &variant).unwrap();
+        let header: Vec<&str> = vertex.lines().take(8).collect();
+        assert_eq!(
+            header,
+            [
+                "#version 300 es",
+                "#extension GL_OVR_multiview2 : require",
+                "layout(num_views = 2) in;",
+                "precision mediump float;",
+                "precision highp int;",
+                "precision lowp sampler2DShadow;",
+                "#define LIGHTS 4",
+                "#define SKINNED 1",
+            ]
+        );
+
+        let default = generate_vertex_stage_str(&header_source(), &[]).unwrap();
+        assert!(default.starts_with(
+            "#version 300 es\n\
+             #extension GL_OVR_multiview2 : require\n\
+             layout(num_views = 2) in;\n\
+             precision mediump float;\n\
+             precision highp int;\n\
+             precision lowp sampler2DShadow;\n\
+             #define LIGHTS 1\n\
+             struct "
+        ));
+    }
+
+    #[test]
+    fn structs_follow_their_dependencies() {
+        let vertex = generate_vertex_stage_str(&header_source(), &[]).unwrap();
+        let struct_names: Vec<&str> = vertex
+            .lines()
+            .filter_map(|line| line.strip_prefix("struct "))
+            .collect();
+        assert_eq!(struct_names, ["Shadow {", "Light {", "Fog {", "Scene {"]);
+        assert!(vertex.contains("struct Scene {\n Light lights[2];\n Fog fog;\n};\n"));
+        let scene = vertex.find("struct Scene").unwrap();
+        assert!(vertex.find("uniform SceneBlock").unwrap() > scene);
+    }
+
+    #[test]
+    fn vertex_only_source_generates_an_empty_fragment_stage() {
+        let source = shader_source_from_str(
+            r#"
+            name = "particles"
+            varyings = [
+                { kind = "Vec3", name = "out_position" },
+                { kind = "Vec3", name = "out_velocity" },
+            ]
+            imported_functions = []
+
+            [common_uniforms]
+            uniforms = [{ kind = "Float", name = "delta_time" }]
+            uniform_blocks = []
+
+            [vertex_shader]
+            version = "300 es"
+            import_fn = []
+            main_fn = "out_velocity = velocity;\nout_position = position + velocity * delta_time;"
+            attributes = [
+                { layout_loc = 0, kind = "Vec3", name = "position" },
+                { layout_loc = 1, kind = "Vec3", name = "velocity" },
+            ]
+            [vertex_shader.uniform_collection]
+            uniforms = []
+            uniform_blocks = []
+
+            [transform_feedback]
+            mode = "Separate"
+            varyings = ["out_position", "out_velocity"]
+            "#,
+        )
+        .unwrap();
+        assert!(source.fragment_shader.is_none());
+        assert!(source.fragment_outputs().is_empty());
+
+        let (vertex, fragment) = generate_shader_str_from_single_source(&source).unwrap();
+        assert_eq!(
+            fragment,
+            "#version 300 es\nprecision highp float;\nvoid main() {}\n"
+        );
+        assert!(vertex.contains("out vec3 out_position;\nout vec3 out_velocity;\n"));
+        assert!(vertex.contains("uniform float delta_time;\n"));
+
+        let mut missing_varying = source.clone();
+        let transform_feedback = missing_varying.transform_feedback.as_mut().unwrap();
+        transform_feedback.varyings[1] = "speed".into();
+        assert!(matches!(
+            generate_shader_str_from_single_source(&missing_varying),
+            Err(ShaderGenError::InvalidSource(errors))
+                if errors == [ValidationError::UnknownFeedbackVarying { name: "speed".into() }]
+        ));
+    }
 }
@@ -1,4 +1,4 @@
-use webgl2_shader_definition::*;
+use crate::*;
 
 pub fn get_pbr_functions() -> Vec<FunctionDefinition> {
     // GLSL functions taken from https://learnopengl.com/PBR/Lighting
@@ -1,7 +1,36 @@
 pub mod lighting_functions;
 
+use crate::FunctionDefinition;
+
+/// Prefix of the `ImportFn` paths that name a built-in file instead of a file of the project,
+/// `{ type = "ImportFn", path = "builtin:pbr" }`. The proc macros and the runtime generator both
+/// resolve them.
+pub const BUILTIN_PREFIX: &str = "builtin:";
+
+/// Names of the built-in files, the path of an import is the name with `BUILTIN_PREFIX`
+pub const BUILTIN_FILES: &[&str] = &[
+    "dither",
+    "fast_hash",
+    "gerstner_waves",
+    "gerstner_waves_normal",
+    "pbr",
+];
+
+/// Function definitions of a built-in file, `file` is the path without `BUILTIN_PREFIX`.
+/// The functions keep their GLSL names, `builtin:dither` defines `dither_fn`.
+pub fn builtin_functions(file: &str) -> Option<Vec<FunctionDefinition>> {
+    match file {
+        "dither" => Some(vec![dither::definition()]),
+        "fast_hash" => Some(vec![fast_hash::definition()]),
+        "gerstner_waves" => Some(vec![gerstner_waves::definition()]),
+        "gerstner_waves_normal" => Some(vec![gerstner_waves_normal::definition()]),
+        "pbr" => Some(lighting_functions::get_pbr_functions()),
+        _ => None,
+    }
+}
+
 pub mod dither{
-    use webgl2_shader_definition::*;
+    use crate::*;
     pub const NAME: &str = "dither_fn";
     pub const PARAMS: &str = "vec2 screen_position_px, float in_value";
     pub const FN: &str = r#"
@@ -30,7 +59,7 @@ return in_value - DITHER_THRESHOLDS[index];"#;
 }
 
 pub mod fast_hash {
-    use webgl2_shader_definition::*;
+    use crate::*;
     pub const NAME: &str = "fast_hash";
     pub const PARAMS: &str = "vec2 v";
     pub const FN: &str = r#"
@@ -55,7 +84,7 @@ return fract( state * state * (3571. * 2.));
 }
 
 pub mod gerstner_waves {
-    use webgl2_shader_definition::*;
+    use crate::*;
 
     pub const NAME: &str = "gerstner_waves";
     pub const PARAMS: &str =
@@ -81,7 +110,7 @@ return vec2(a * cos(f), a * sin(f));
 }
 
 pub mod gerstner_waves_normal {
-    use webgl2_shader_definition::*;
+    use crate::*;
 
     pub const NAME: &str = "gerstner_waves_normal";
     pub const PARAMS: &str =
@@ -0,0 +1,621 @@
+use std::fmt;
+
+use glam::*;
+
+use crate::{ShaderStruct, ShaderUniform, ShaderUniformBlock, UniformKind, WebGLDataType};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Std140Error {
+    /// A member uses a struct that is not in the structs given to the layout
+    UnknownStruct {
+        name: String,
+        struct_name: String,
+    },
+    /// `void` and opaque types cannot be stored in a uniform block
+    InvalidType {
+        name: String,
+        kind: WebGLDataType,
+    },
+    RecursiveStruct {
+        name: String,
+    },
+    /// The block has no member with this name
+    UnknownMember {
+        name: String,
+    },
+    TypeMismatch {
+        name: String,
+        expected: WebGLDataType,
+        got: WebGLDataType,
+    },
+    /// More values than the array member can hold
+    IndexOutOfBounds {
+        name: String,
+        index: u32,
+        length: u32,
+    },
+}
+
+impl fmt::Display for Std140Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Std140Error::UnknownStruct { name, struct_name } => write!(
+                f,
+                "member '{}' uses struct '{}' which is not declared",
+                name, struct_name
+            ),
+            Std140Error::InvalidType { name, kind } => write!(
+                f,
+                "member '{}' of type {} cannot be stored in a uniform block",
+                name,
+                kind.as_str()
+            ),
+            Std140Error::RecursiveStruct { name } => {
+                write!(f, "struct '{}' contains itself", name)
+            }
+            Std140Error::UnknownMember { name } => {
+                write!(f, "uniform block has no member named '{}'", name)
+            }
+            Std140Error::TypeMismatch {
+                name,
+                expected,
+                got,
+            } => write!(
+                f,
+                "member '{}' is a {} but a {} value was written",
+                name,
+                expected.as_str(),
+                got.as_str()
+            ),
+            Std140Error::IndexOutOfBounds {
+                name,
+                index,
+                length,
+            } => write!(
+                f,
+                "index {} is out of bounds for member '{}' of length {}",
+                index, name, length
+            ),
+        }
+    }
+}
+
+/// Placement of a single non-struct member inside a std140 block, every offset is in bytes.
+/// Members of structs are flattened with their full GLSL name (`lights[2].color`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Std140Member {
+    pub name: String,
+    pub kind: WebGLDataType,
+    pub offset: u32,
+    /// Size of the member, for arrays this includes the padding of every element
+    pub size: u32,
+    pub align: u32,
+    pub array_length: Option<u32>,
+    /// Distance between two elements of an array, 0 if the member is not an array
+    pub array_stride: u32,
+    /// Distance between two columns of a matrix, 0 if the member is not a matrix
+    pub matrix_stride: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Std140Layout {
+    /// Size of the whole block, the minimum size of the buffer bound to it
+    pub size: u32,
+    pub members: Vec<Std140Member>,
+}
+
+impl Std140Layout {
+    pub fn member(&self, name: &str) -> Option<&Std140Member> {
+        self.members.iter().find(|member| member.name == name)
+    }
+}
+
+fn round_up(value: u32, align: u32) -> u32 {
+    value.div_ceil(align) * align
+}
+
+// (size, base alignment, matrix stride) of a type that is not inside an array
+fn data_type_layout(kind: WebGLDataType) -> Option<(u32, u32, u32)> {
+    match kind {
+        WebGLDataType::Bool | WebGLDataType::Int | WebGLDataType::Uint | WebGLDataType::Float => {
+            Some((4, 4, 0))
+        }
+        WebGLDataType::Vec2
+        | WebGLDataType::BVec2
+        | WebGLDataType::IVec2
+        | WebGLDataType::UVec2 => Some((8, 8, 0)),
+        WebGLDataType::Vec3
+        | WebGLDataType::BVec3
+        | WebGLDataType::IVec3
+        | WebGLDataType::UVec3 => Some((12, 16, 0)),
+        WebGLDataType::Vec4
+        | WebGLDataType::BVec4
+        | WebGLDataType::IVec4
+        | WebGLDataType::UVec4 => Some((16, 16, 0)),
+        // Matrices are stored as arrays of column vectors, each column is padded to a vec4
+        WebGLDataType::Mat2 => Some((32, 16, 16)),
+        WebGLDataType::Mat3 => Some((48, 16, 16)),
+        WebGLDataType::Mat4 => Some((64, 16, 16)),
+        _ => None,
+    }
+}
+
+struct LayoutBuilder<'a> {
+    structs: &'a [ShaderStruct],
+    struct_stack: Vec<&'a str>,
+    members: Vec<Std140Member>,
+    offset: u32,
+}
+
+impl<'a> LayoutBuilder<'a> {
+    fn place(&mut self, uniform: &'a ShaderUniform, prefix: &str) -> Result<(), Std140Error> {
+        let name = format!("{}{}", prefix, uniform.name);
+        match &uniform.kind {
+            UniformKind::Data(kind) => {
+                let (size, align, matrix_stride) =
+                    data_type_layout(*kind).ok_or_else(|| Std140Error::InvalidType {
+                        name: name.clone(),
+                        kind: *kind,
+                    })?;
+                // Array elements are aligned like a vec4
+                let (size, align, array_stride) = match uniform.array_length {
+                    Some(len) => {
+                        let stride = round_up(size, 16);
+                        (stride * len, 16, stride)
+                    }
+                    None => (size, align, 0),
+                };
+                self.offset = round_up(self.offset, align);
+                self.members.push(Std140Member {
+                    name,
+                    kind: *kind,
+                    offset: self.offset,
+                    size,
+                    align,
+                    array_length: uniform.array_length,
+                    array_stride,
+                    matrix_stride,
+                });
+                self.offset += size;
+            }
+            UniformKind::Struct(struct_name) => {
+                let shader_struct = self
+                    .structs
+                    .iter()
+                    .find(|s| &s.name == struct_name)
+                    .ok_or_else(|| Std140Error::UnknownStruct {
+                        name: name.clone(),
+                        struct_name: struct_name.clone(),
+                    })?;
+                if self.struct_stack.contains(&struct_name.as_str()) {
+                    return Err(Std140Error::RecursiveStruct {
+                        name: struct_name.clone(),
+                    });
+                }
+                self.struct_stack.push(struct_name);
+                // Structs are aligned like a vec4 and padded to a multiple of it
+                let element_prefixes = match uniform.array_length {
+                    Some(len) => (0..len).map(|i| format!("{}[{}].", name, i)).collect(),
+                    None => vec![format!("{}.", name)],
+                };
+                for element_prefix in element_prefixes {
+                    self.offset = round_up(self.offset, 16);
+                    for member in &shader_struct.members {
+                        self.place(member, &element_prefix)?;
+                    }
+                    self.offset = round_up(self.offset, 16);
+                }
+                self.struct_stack.pop();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ShaderUniformBlock {
+    /// Computes the std140 offset, size and alignment of every member of the block.
+    /// `structs` are the struct declarations of the `ShaderSource` the block belongs to.
+    pub fn std140_layout(&self, structs: &[ShaderStruct]) -> Result<Std140Layout, Std140Error> {
+        let mut builder = LayoutBuilder {
+            structs,
+            struct_stack: Vec::new(),
+            members: Vec::new(),
+            offset: 0,
+        };
+        for uniform in &self.uniforms {
+            builder.place(uniform, "")?;
+        }
+        Ok(Std140Layout {
+            size: round_up(builder.offset, 16),
+            members: builder.members,
+        })
+    }
+}
+
+/// A Rust value that can be stored in a std140 uniform block member of type `DATA_TYPE`
+pub trait Std140Value {
+    const DATA_TYPE: WebGLDataType;
+    /// Writes the value at the start of `dst`, matrix columns are `matrix_stride` bytes apart
+    fn write_std140(&self, dst: &mut [u8], matrix_stride: usize);
+}
+
+fn write_bytes(dst: &mut [u8], bytes: &[u8]) {
+    dst[..bytes.len()].copy_from_slice(bytes);
+}
+
+fn write_columns<const R: usize>(dst: &mut [u8], columns: &[[f32; R]], matrix_stride: usize) {
+    for (index, column) in columns.iter().enumerate() {
+        write_bytes(
+            &mut dst[index * matrix_stride..],
+            bytemuck::cast_slice(column.as_slice()),
+        );
+    }
+}
+
+macro_rules! impl_std140_value {
+    ($type:ty, $data_type:ident, |$value:ident| $bytes:expr) => {
+        impl Std140Value for $type {
+            const DATA_TYPE: WebGLDataType = WebGLDataType::$data_type;
+            fn write_std140(&self, dst: &mut [u8], _matrix_stride: usize) {
+                let $value = self;
+                write_bytes(dst, bytemuck::cast_slice(&$bytes));
+            }
+        }
+    };
+}
+
+impl_std140_value!(f32, Float, |v| [*v]);
+impl_std140_value!(i32, Int, |v| [*v]);
+impl_std140_value!(u32, Uint, |v| [*v]);
+impl_std140_value!(bool, Bool, |v| [*v as u32]);
+impl_std140_value!(Vec2, Vec2, |v| v.to_array());
+impl_std140_value!(Vec3, Vec3, |v| v.to_array());
+impl_std140_value!(Vec3A, Vec3, |v| v.to_array());
+impl_std140_value!(Vec4, Vec4, |v| v.to_array());
+impl_std140_value!(IVec2, IVec2, |v| v.to_array());
+impl_std140_value!(IVec3, IVec3, |v| v.to_array());
+impl_std140_value!(IVec4, IVec4, |v| v.to_array());
+impl_std140_value!(UVec2, UVec2, |v| v.to_array());
+impl_std140_value!(UVec3, UVec3, |v| v.to_array());
+impl_std140_value!(UVec4, UVec4, |v| v.to_array());
+impl_std140_value!(BVec2, BVec2, |v| [v.x as u32, v.y as u32]);
+impl_std140_value!(BVec3, BVec3, |v| [v.x as u32, v.y as u32, v.z as u32]);
+impl_std140_value!(BVec4, BVec4, |v| [
+    v.x as u32, v.y as u32, v.z as u32, v.w as u32
+]);
+
+impl Std140Value for Mat2 {
+    const DATA_TYPE: WebGLDataType = WebGLDataType::Mat2;
+    fn write_std140(&self, dst: &mut [u8], matrix_stride: usize) {
+        write_columns(dst, &self.to_cols_array_2d(), matrix_stride);
+    }
+}
+
+impl Std140Value for Mat3 {
+    const DATA_TYPE: WebGLDataType = WebGLDataType::Mat3;
+    fn write_std140(&self, dst: &mut [u8], matrix_stride: usize) {
+        write_columns(dst, &self.to_cols_array_2d(), matrix_stride);
+    }
+}
+
+impl Std140Value for Mat4 {
+    const DATA_TYPE: WebGLDataType = WebGLDataType::Mat4;
+    fn write_std140(&self, dst: &mut [u8], matrix_stride: usize) {
+        write_columns(dst, &self.to_cols_array_2d(), matrix_stride);
+    }
+}
+
+/// Packs values into a byte buffer following a `Std140Layout`, the bytes can be uploaded as is to
+/// the uniform buffer bound to the block. Members that are never set are left as zeros.
+#[derive(Debug, Clone)]
+pub struct Std140Writer {
+    layout: Std140Layout,
+    data: Vec<u8>,
+}
+
+impl Std140Writer {
+    pub fn new(layout: Std140Layout) -> Self {
+        let data = vec![0; layout.size as usize];
+        Self { layout, data }
+    }
+
+    pub fn layout(&self) -> &Std140Layout {
+        &self.layout
+    }
+
+    fn typed_member<T: Std140Value>(&self, name: &str) -> Result<&Std140Member, Std140Error> {
+        let member = self
+            .layout
+            .member(name)
+            .ok_or_else(|| Std140Error::UnknownMember {
+                name: name.to_string(),
+            })?;
+        if member.kind != T::DATA_TYPE {
+            return Err(Std140Error::TypeMismatch {
+                name: name.to_string(),
+                expected: member.kind,
+                got: T::DATA_TYPE,
+            });
+        }
+        Ok(member)
+    }
+
+    /// Writes `value` to the member called `name`, for array members this sets the first element
+    pub fn set<T: Std140Value>(&mut self, name: &str, value: &T) -> Result<(), Std140Error> {
+        self.set_array(name, std::slice::from_ref(value))
+    }
+
+    /// Writes `values` to the first elements of the array member called `name`
+    pub fn set_array<T: Std140Value>(
+        &mut self,
+        name: &str,
+        values: &[T],
+    ) -> Result<(), Std140Error> {
+        let member = self.typed_member::<T>(name)?.clone();
+        let length = member.array_length.unwrap_or(1);
+        if values.len() > length as usize {
+            return Err(Std140Error::IndexOutOfBounds {
+                name: name.to_string(),
+                index: values.len() as u32 - 1,
+                length,
+            });
+        }
+        for (index, value) in values.iter().enumerate() {
+            let offset = (member.offset + member.array_stride * index as u32) as usize;
+            value.write_std140(&mut self.data[offset..], member.matrix_stride as usize);
+        }
+        Ok(())
+    }
+
+    /// Writes `value` to the element `index` of the array member called `name`
+    pub fn set_element<T: Std140Value>(
+        &mut self,
+        name: &str,
+        index: u32,
+        value: &T,
+    ) -> Result<(), Std140Error> {
+        let member = self.typed_member::<T>(name)?.clone();
+        let length = member.array_length.unwrap_or(1);
+        if index >= length {
+            return Err(Std140Error::IndexOutOfBounds {
+                name: name.to_string(),
+                index,
+                length,
+            });
+        }
+        let offset = (member.offset + member.array_stride * index) as usize;
+        value.write_std140(&mut self.data[offset..], member.matrix_stride as usize);
+        Ok(())
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn member(
+        kind: impl Into<UniformKind>,
+        name: &str,
+        array_length: Option<u32>,
+    ) -> ShaderUniform {
+        ShaderUniform {
+            array_length,
+            kind: kind.into(),
+            name: name.into(),
+            when: None,
+        }
+    }
+
+    fn block(uniforms: Vec<ShaderUniform>) -> ShaderUniformBlock {
+        ShaderUniformBlock {
+            binding_number: 0,
+            name: "Block".into(),
+            uniforms,
+            when: None,
+        }
+    }
+
+    fn offset(layout: &Std140Layout, name: &str) -> u32 {
+        layout.member(name).unwrap().offset
+    }
+
+    #[test]
+    fn vec3_is_followed_by_a_packed_float() {
+        let layout = block(vec![
+            member(WebGLDataType::Vec3, "direction", None),
+            member(WebGLDataType::Float, "intensity", None),
+        ])
+        .std140_layout(&[])
+        .unwrap();
+        let direction = layout.member("direction").unwrap();
+        assert_eq!(direction.offset, 0);
+        assert_eq!(direction.size, 12);
+        assert_eq!(direction.align, 16);
+        assert_eq!(offset(&layout, "intensity"), 12);
+        assert_eq!(layout.size, 16);
+    }
+
+    #[test]
+    fn mat3_is_three_padded_columns() {
+        let layout = block(vec![
+            member(WebGLDataType::Float, "scale", None),
+            member(WebGLDataType::Mat3, "normal_matrix", None),
+            member(WebGLDataType::Float, "after", None),
+        ])
+        .std140_layout(&[])
+        .unwrap();
+        let matrix = layout.member("normal_matrix").unwrap();
+        assert_eq!(matrix.offset, 16);
+        assert_eq!(matrix.size, 48);
+        assert_eq!(matrix.matrix_stride, 16);
+        assert_eq!(offset(&layout, "after"), 64);
+        assert_eq!(layout.size, 80);
+    }
+
+    #[test]
+    fn float_array_has_a_vec4_stride() {
+        let layout = block(vec![
+            member(WebGLDataType::Float, "weights", Some(4)),
+            member(WebGLDataType::Float, "after", None),
+        ])
+        .std140_layout(&[])
+        .unwrap();
+        let weights = layout.member("weights").unwrap();
+        assert_eq!(weights.array_stride, 16);
+        assert_eq!(weights.size, 64);
+        assert_eq!(offset(&layout, "after"), 64);
+    }
+
+    #[test]
+    fn struct_array_elements_are_rounded_up_to_16() {
+        let light = ShaderStruct {
+            name: "Light".into(),
+            members: vec![
+                member(WebGLDataType::Vec2, "position", None),
+                member(WebGLDataType::Float, "radius", None),
+            ],
+        };
+        let layout = block(vec![
+            member(WebGLDataType::Float, "count", None),
+            member("Light", "lights", Some(2)),
+            member(WebGLDataType::Float, "after", None),
+        ])
+        .std140_layout(&[light])
+        .unwrap();
+        assert_eq!(offset(&layout, "lights[0].position"), 16);
+        assert_eq!(offset(&layout, "lights[0].radius"), 24);
+        assert_eq!(offset(&layout, "lights[1].position"), 32);
+        assert_eq!(offset(&layout, "lights[1].radius"), 40);
+        assert_eq!(offset(&layout, "after"), 48);
+        assert_eq!(layout.size, 64);
+    }
+
+    #[test]
+    fn recursive_and_unknown_structs_are_rejected() {
+        let node = ShaderStruct {
+            name: "Node".into(),
+            members: vec![member("Node", "child", None)],
+        };
+        let layout = block(vec![member("Node", "root", None)]).std140_layout(&[node]);
+        assert_eq!(
+            layout,
+            Err(Std140Error::RecursiveStruct {
+                name: "Node".into()
+            })
+        );
+
+        let layout = block(vec![member("Light", "light", None)]).std140_layout(&[]);
+        assert_eq!(
+            layout,
+            Err(Std140Error::UnknownStruct {
+                name: "light".into(),
+                struct_name: "Light".into()
+            })
+        );
+    }
+
+    fn floats(bytes: &[u8]) -> Vec<f32> {
+        bytes
+            .chunks(4)
+            .map(|chunk| f32::from_ne_bytes(chunk.try_into().unwrap()))
+            .collect()
+    }
+
+    fn uints(bytes: &[u8]) -> Vec<u32> {
+        bytes
+            .chunks(4)
+            .map(|chunk| u32::from_ne_bytes(chunk.try_into().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn writer_packs_values() {
+        let layout = block(vec![
+            member(WebGLDataType::Vec3, "direction", None),
+            member(WebGLDataType::Float, "intensity", None),
+            member(WebGLDataType::Mat3, "basis", None),
+            member(WebGLDataType::Float, "weights", Some(3)),
+            member(WebGLDataType::UVec2, "ids", None),
+        ])
+        .std140_layout(&[])
+        .unwrap();
+        let mut writer = Std140Writer::new(layout);
+        writer.set("direction", &Vec3::new(1.0, 2.0, 3.0)).unwrap();
+        writer.set("intensity", &4.0f32).unwrap();
+        writer
+            .set(
+                "basis",
+                &Mat3::from_cols(
+                    Vec3::new(5.0, 6.0, 7.0),
+                    Vec3::new(8.0, 9.0, 10.0),
+                    Vec3::new(11.0, 12.0, 13.0),
+                ),
+            )
+            .unwrap();
+        writer.set_array("weights", &[14.0f32, 15.0]).unwrap();
+        writer.set_element("weights", 2, &16.0f32).unwrap();
+        writer.set("ids", &UVec2::new(17, 18)).unwrap();
+
+        let bytes = writer.as_bytes();
+        assert_eq!(bytes.len(), 128);
+        #[rustfmt::skip]
+        let expected = [
+            1.0, 2.0, 3.0, 4.0,
+            5.0, 6.0, 7.0, 0.0,
+            8.0, 9.0, 10.0, 0.0,
+            11.0, 12.0, 13.0, 0.0,
+            14.0, 0.0, 0.0, 0.0,
+            15.0, 0.0, 0.0, 0.0,
+            16.0, 0.0, 0.0, 0.0,
+        ];
+        assert_eq!(floats(&bytes[..112]), expected);
+        assert_eq!(uints(&bytes[112..]), [17, 18, 0, 0]);
+    }
+
+    #[test]
+    fn writer_rejects_wrong_types_and_indices() {
+        let layout = block(vec![member(WebGLDataType::Vec4, "colors", Some(2))])
+            .std140_layout(&[])
+            .unwrap();
+        let mut writer = Std140Writer::new(layout);
+        assert_eq!(
+            writer.set("colors", &Vec3::ONE),
+            Err(Std140Error::TypeMismatch {
+                name: "colors".into(),
+                expected: WebGLDataType::Vec4,
+                got: WebGLDataType::Vec3,
+            })
+        );
+        assert_eq!(
+            writer.set_element("colors", 2, &Vec4::ONE),
+            Err(Std140Error::IndexOutOfBounds {
+                name: "colors".into(),
+                index: 2,
+                length: 2,
+            })
+        );
+        assert_eq!(
+            writer.set_array("colors", &[Vec4::ONE; 3]),
+            Err(Std140Error::IndexOutOfBounds {
+                name: "colors".into(),
+                index: 2,
+                length: 2,
+            })
+        );
+        assert_eq!(
+            writer.set("color", &Vec4::ONE),
+            Err(Std140Error::UnknownMember {
+                name: "color".into()
+            })
+        );
+        assert!(writer.as_bytes().iter().all(|byte| *byte == 0));
+    }
+}
@@ -0,0 +1,97 @@
+use bytemuck::{Pod, Zeroable};
+use glam::*;
+
+use crate::{ShaderStruct, ShaderUniform, ShaderUniformBlock, Std140Value};
+
+/// A Rust struct that mirrors a GLSL uniform block, usually implemented with
+/// `#[derive(UniformBlock)]`. `Std140` is the same data with the std140 padding made explicit,
+/// it can be uploaded as is to the `GlUniformBuffer` bound to the block.
+pub trait UniformBlock {
+    type Std140: Pod;
+    /// Name of the block in the generated GLSL
+    const NAME: &'static str;
+
+    fn to_std140(&self) -> Self::Std140;
+
+    /// Members of the block, in declaration order
+    fn uniforms() -> Vec<ShaderUniform>;
+
+    /// Declarations of the structs the members use (the fields that are other `UniformBlock`
+    /// structs), dependencies first. They go in `ShaderSource::structs` next to the block.
+    fn structs() -> Vec<ShaderStruct> {
+        Vec::new()
+    }
+
+    fn uniform_block(binding_number: u32) -> ShaderUniformBlock {
+        ShaderUniformBlock {
+            binding_number,
+            name: Self::NAME.to_string(),
+            uniforms: Self::uniforms(),
+            when: None,
+        }
+    }
+}
+
+/// Adds the struct declaration of `B`, named `B::NAME`, and the ones it uses to `structs`. Structs
+/// that are already in the list are skipped.
+pub fn push_block_struct<B: UniformBlock>(structs: &mut Vec<ShaderStruct>) {
+    let block_struct = ShaderStruct {
+        name: B::NAME.to_string(),
+        members: B::uniforms(),
+    };
+    for shader_struct in B::structs().into_iter().chain([block_struct]) {
+        if !structs.iter().any(|other| other.name == shader_struct.name) {
+            structs.push(shader_struct);
+        }
+    }
+}
+
+/// How a field of a `#[derive(UniformBlock)]` struct is stored in its std140 companion struct
+pub trait Std140Field: Std140Value {
+    /// The value on its own, matrix columns are padded to a vec4
+    type Packed: Pod;
+    /// The value as an array element, padded to a multiple of 16 bytes
+    type ArrayElement: Pod;
+
+    fn to_packed(&self) -> Self::Packed {
+        let mut packed = Self::Packed::zeroed();
+        self.write_std140(bytemuck::bytes_of_mut(&mut packed), 16);
+        packed
+    }
+
+    fn to_array_element(&self) -> Self::ArrayElement {
+        let mut element = Self::ArrayElement::zeroed();
+        self.write_std140(bytemuck::bytes_of_mut(&mut element), 16);
+        element
+    }
+}
+
+macro_rules! impl_std140_field {
+    ($type:ty, $packed:ty, $array_element:ty) => {
+        impl Std140Field for $type {
+            type Packed = $packed;
+            type ArrayElement = $array_element;
+        }
+    };
+}
+
+impl_std140_field!(f32, f32, [f32; 4]);
+impl_std140_field!(i32, i32, [i32; 4]);
+impl_std140_field!(u32, u32, [u32; 4]);
+impl_std140_field!(bool, u32, [u32; 4]);
+impl_std140_field!(Vec2, [f32; 2], [f32; 4]);
+impl_std140_field!(Vec3, [f32; 3], [f32; 4]);
+impl_std140_field!(Vec3A, [f32; 3], [f32; 4]);
+impl_std140_field!(Vec4, [f32; 4], [f32; 4]);
+impl_std140_field!(IVec2, [i32; 2], [i32; 4]);
+impl_std140_field!(IVec3, [i32; 3], [i32; 4]);
+impl_std140_field!(IVec4, [i32; 4], [i32; 4]);
+impl_std140_field!(UVec2, [u32; 2], [u32; 4]);
+impl_std140_field!(UVec3, [u32; 3], [u32; 4]);
+impl_std140_field!(UVec4, [u32; 4], [u32; 4]);
+impl_std140_field!(BVec2, [u32; 2], [u32; 4]);
+impl_std140_field!(BVec3, [u32; 3], [u32; 4]);
+impl_std140_field!(BVec4, [u32; 4], [u32; 4]);
+impl_std140_field!(Mat2, [[f32; 4]; 2], [[f32; 4]; 2]);
+impl_std140_field!(Mat3, [[f32; 4]; 3], [[f32; 4]; 3]);
+impl_std140_field!(Mat4, [[f32; 4]; 4], [[f32; 4]; 4]);
@@ -0,0 +1,863 @@
+use std::fmt;
+
+use crate::{
+    parse_condition, InterpolationQualifier, ShaderAttribute, ShaderKeyword, ShaderSource,
+    ShaderStage, ShaderStageKind, ShaderStruct, ShaderUniform, UniformCollection, UniformKind,
+    VariantError, WebGLDataType,
+};
+
+/// The kind of declaration that introduced a name into a stage's global scope
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeclarationKind {
+    Uniform,
+    UniformBlock,
+    Struct,
+    Varying,
+    Attribute,
+}
+
+impl DeclarationKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeclarationKind::Uniform => "uniform",
+            DeclarationKind::UniformBlock => "uniform block",
+            DeclarationKind::Struct => "struct",
+            DeclarationKind::Varying => "varying",
+            DeclarationKind::Attribute => "attribute",
+        }
+    }
+}
+
+/// A problem in the interface between the stages of a `ShaderSource`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    /// Two attributes (vertex inputs or fragment outputs) of the same stage share a `layout_loc`
+    DuplicateAttributeLocation {
+        stage: ShaderStageKind,
+        location: u32,
+        first: String,
+        second: String,
+    },
+    /// Vertex inputs cannot be booleans or opaque types, fragment outputs can only be
+    /// float, int or uint scalars and vectors
+    InvalidAttributeType {
+        stage: ShaderStageKind,
+        name: String,
+        kind: WebGLDataType,
+    },
+    /// Varyings cannot be booleans or opaque types
+    InvalidVaryingType { name: String, kind: WebGLDataType },
+    /// GLSL ES 3.00 requires integer varyings to use the `flat` interpolation qualifier
+    IntegerVaryingNotFlat { name: String, kind: WebGLDataType },
+    /// Uniforms cannot be `void`, members of a uniform block (or of the structs they use) cannot
+    /// be opaque types
+    InvalidUniformType { name: String, kind: WebGLDataType },
+    /// A uniform or struct member is declared as an array of length 0
+    InvalidArrayLength { name: String },
+    /// A uniform or struct member uses a struct that is not declared in `structs`
+    UnknownStruct { name: String, struct_name: String },
+    DuplicateStruct { name: String },
+    /// GLSL structs need at least one member
+    EmptyStruct { name: String },
+    /// A struct contains itself, directly or through the structs of its members
+    RecursiveStruct { name: String },
+    /// The same name is declared twice in the global scope of a stage
+    NameClash {
+        stage: ShaderStageKind,
+        name: String,
+        first: DeclarationKind,
+        second: DeclarationKind,
+    },
+    /// A default precision is given for a type other than float, int or a sampler
+    InvalidPrecisionType {
+        stage: ShaderStageKind,
+        kind: WebGLDataType,
+    },
+    /// A transform feedback varying is not an output of the vertex stage
+    UnknownFeedbackVarying { name: String },
+    DuplicateFeedbackVarying { name: String },
+    DuplicateKeyword { name: String },
+    /// An `Int` keyword without values or with a default that is not one of its values
+    InvalidKeyword { name: String },
+    /// A `when` condition cannot be parsed or names an undeclared keyword
+    Variant(VariantError),
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::DuplicateAttributeLocation {
+                stage,
+                location,
+                first,
+                second,
+            } => write!(
+                f,
+                "{} stage attributes '{}' and '{}' both use layout location {}",
+                stage.as_str(),
+                first,
+                second,
+                location
+            ),
+            ValidationError::InvalidAttributeType { stage, name, kind } => write!(
+                f,
+                "{} stage attribute '{}' cannot be of type {}",
+                stage.as_str(),
+                name,
+                kind.as_str()
+            ),
+            ValidationError::InvalidVaryingType { name, kind } => {
+                write!(f, "varying '{}' cannot be of type {}", name, kind.as_str())
+            }
+            ValidationError::IntegerVaryingNotFlat { name, kind } => write!(
+                f,
+                "varying '{}' of integer type {} must use the Flat interpolation qualifier",
+                name,
+                kind.as_str()
+            ),
+            ValidationError::InvalidUniformType { name, kind } => {
+                write!(f, "uniform '{}' cannot be of type {}", name, kind.as_str())
+            }
+            ValidationError::InvalidArrayLength { name } => {
+                write!(f, "'{}' is declared as an array of length 0", name)
+            }
+            ValidationError::UnknownStruct { name, struct_name } => write!(
+                f,
+                "'{}' uses struct '{}' but it is not declared in structs",
+                name, struct_name
+            ),
+            ValidationError::DuplicateStruct { name } => {
+                write!(f, "struct '{}' is declared more than once", name)
+            }
+            ValidationError::EmptyStruct { name } => {
+                write!(f, "struct '{}' needs at least one member", name)
+            }
+            ValidationError::RecursiveStruct { name } => {
+                write!(f, "struct '{}' contains itself", name)
+            }
+            ValidationError::NameClash {
+                stage,
+                name,
+                first,
+                second,
+            } => write!(
+                f,
+                "'{}' is declared twice in the {} stage, first as {} and then as {}",
+                name,
+                stage.as_str(),
+                first.as_str(),
+                second.as_str()
+            ),
+            ValidationError::InvalidPrecisionType { stage, kind } => write!(
+                f,
+                "{} stage cannot set a default precision for {}, only float, int and samplers",
+                stage.as_str(),
+                kind.as_str()
+            ),
+            ValidationError::UnknownFeedbackVarying { name } => write!(
+                f,
+                "transform feedback captures '{}' but the vertex stage has no output with that name",
+                name
+            ),
+            ValidationError::DuplicateFeedbackVarying { name } => {
+                write!(f, "transform feedback captures '{}' more than once", name)
+            }
+            ValidationError::DuplicateKeyword { name } => {
+                write!(f, "keyword '{}' is declared more than once", name)
+            }
+            ValidationError::InvalidKeyword { name } => write!(
+                f,
+                "keyword '{}' needs at least one value and a default that is one of them",
+                name
+            ),
+            ValidationError::Variant(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+fn validate_attributes(
+    errors: &mut Vec<ValidationError>,
+    attributes: &[ShaderAttribute],
+    stage: ShaderStageKind,
+) {
+    for (index, attr) in attributes.iter().enumerate() {
+        let valid_type = match stage {
+            ShaderStageKind::Vertex => {
+                !(attr.kind == WebGLDataType::Void || attr.kind.is_bool() || attr.kind.is_opaque())
+            }
+            ShaderStageKind::Fragment => {
+                !(attr.kind == WebGLDataType::Void
+                    || attr.kind.is_bool()
+                    || attr.kind.is_opaque()
+                    || attr.kind.is_matrix())
+            }
+        };
+        if !valid_type {
+            errors.push(ValidationError::InvalidAttributeType {
+                stage,
+                name: attr.name.clone(),
+                kind: attr.kind,
+            });
+        }
+        if let Some(other) = attributes[..index]
+            .iter()
+            .find(|other| other.layout_loc == attr.layout_loc)
+        {
+            errors.push(ValidationError::DuplicateAttributeLocation {
+                stage,
+                location: attr.layout_loc,
+                first: other.name.clone(),
+                second: attr.name.clone(),
+            });
+        }
+    }
+}
+
+fn validate_precision(
+    errors: &mut Vec<ValidationError>,
+    stage_src: &ShaderStage,
+    stage: ShaderStageKind,
+) {
+    for qualifier in &stage_src.precision {
+        let kind = qualifier.kind;
+        if !(kind == WebGLDataType::Float || kind == WebGLDataType::Int || kind.is_opaque()) {
+            errors.push(ValidationError::InvalidPrecisionType { stage, kind });
+        }
+    }
+}
+
+fn validate_transform_feedback(errors: &mut Vec<ValidationError>, source: &ShaderSource) {
+    let feedback = match &source.transform_feedback {
+        Some(feedback) => feedback,
+        None => return,
+    };
+    for (index, name) in feedback.varyings.iter().enumerate() {
+        let is_output = name == "gl_Position"
+            || name == "gl_PointSize"
+            || source.varyings.iter().any(|vary| &vary.name == name);
+        if !is_output {
+            errors.push(ValidationError::UnknownFeedbackVarying { name: name.clone() });
+        }
+        if feedback.varyings[..index].contains(name) {
+            errors.push(ValidationError::DuplicateFeedbackVarying { name: name.clone() });
+        }
+    }
+}
+
+fn push_collection_names<'a>(
+    names: &mut Vec<(&'a str, DeclarationKind)>,
+    collection: &'a UniformCollection,
+) {
+    for uniform in &collection.uniforms {
+        names.push((&uniform.name, DeclarationKind::Uniform));
+    }
+    // Members of a uniform block without an instance name live in the global scope, next to the
+    // name of the block
+    for block in &collection.uniform_blocks {
+        names.push((&block.name, DeclarationKind::UniformBlock));
+        for uniform in &block.uniforms {
+            names.push((&uniform.name, DeclarationKind::Uniform));
+        }
+    }
+}
+
+fn validate_stage_scope(
+    errors: &mut Vec<ValidationError>,
+    source: &ShaderSource,
+    stage_src: &ShaderStage,
+    stage: ShaderStageKind,
+) {
+    let mut names: Vec<(&str, DeclarationKind)> = Vec::new();
+    for shader_struct in &source.structs {
+        names.push((&shader_struct.name, DeclarationKind::Struct));
+    }
+    push_collection_names(&mut names, &source.common_uniforms);
+    push_collection_names(&mut names, &stage_src.uniform_collection);
+    for vary in &source.varyings {
+        names.push((&vary.name, DeclarationKind::Varying));
+    }
+    for attr in &stage_src.attributes {
+        names.push((&attr.name, DeclarationKind::Attribute));
+    }
+
+    for (index, (name, kind)) in names.iter().enumerate() {
+        if let Some((_, first)) = names[..index].iter().find(|(other, _)| other == name) {
+            // Reported once by `validate_structs`
+            if let (DeclarationKind::Struct, DeclarationKind::Struct) = (first, kind) {
+                continue;
+            }
+            errors.push(ValidationError::NameClash {
+                stage,
+                name: name.to_string(),
+                first: *first,
+                second: *kind,
+            });
+        }
+    }
+}
+
+// Checks the parts of a declaration that are the same for uniforms and struct members
+fn validate_uniform_declaration(
+    errors: &mut Vec<ValidationError>,
+    uniform: &ShaderUniform,
+    structs: &[ShaderStruct],
+) {
+    match &uniform.kind {
+        UniformKind::Data(WebGLDataType::Void) => {
+            errors.push(ValidationError::InvalidUniformType {
+                name: uniform.name.clone(),
+                kind: WebGLDataType::Void,
+            });
+        }
+        UniformKind::Data(_) => {}
+        UniformKind::Struct(struct_name) => {
+            if !structs.iter().any(|s| &s.name == struct_name) {
+                errors.push(ValidationError::UnknownStruct {
+                    name: uniform.name.clone(),
+                    struct_name: struct_name.clone(),
+                });
+            }
+        }
+    }
+    if uniform.array_length == Some(0) {
+        errors.push(ValidationError::InvalidArrayLength {
+            name: uniform.name.clone(),
+        });
+    }
+}
+
+/// Finds a member with an opaque type inside `uniform`, looking through the structs it uses
+fn find_opaque_member<'a>(
+    uniform: &'a ShaderUniform,
+    structs: &'a [ShaderStruct],
+    visited: &mut Vec<&'a str>,
+) -> Option<&'a ShaderUniform> {
+    match &uniform.kind {
+        UniformKind::Data(kind) => {
+            if kind.is_opaque() {
+                Some(uniform)
+            } else {
+                None
+            }
+        }
+        UniformKind::Struct(struct_name) => {
+            if visited.contains(&struct_name.as_str()) {
+                return None;
+            }
+            visited.push(struct_name);
+            let shader_struct = structs.iter().find(|s| &s.name == struct_name)?;
+            shader_struct
+                .members
+                .iter()
+                .find_map(|member| find_opaque_member(member, structs, visited))
+        }
+    }
+}
+
+fn validate_uniform_types(
+    errors: &mut Vec<ValidationError>,
+    collection: &UniformCollection,
+    structs: &[ShaderStruct],
+) {
+    let block_uniforms = collection
+        .uniform_blocks
+        .iter()
+        .flat_map(|block| block.uniforms.iter());
+    for uniform in collection.uniforms.iter().chain(block_uniforms) {
+        validate_uniform_declaration(errors, uniform, structs);
+    }
+    for block in &collection.uniform_blocks {
+        for uniform in &block.uniforms {
+            if let Some(opaque) = find_opaque_member(uniform, structs, &mut Vec::new()) {
+                if let Some(kind) = opaque.kind.data_type() {
+                    errors.push(ValidationError::InvalidUniformType {
+                        name: opaque.name.clone(),
+                        kind,
+                    });
+                }
+            }
+        }
+    }
+}
+
+fn struct_contains<'a>(
+    shader_struct: &'a ShaderStruct,
+    target: &str,
+    structs: &'a [ShaderStruct],
+    visited: &mut Vec<&'a str>,
+) -> bool {
+    for member in &shader_struct.members {
+        if let Some(member_struct) = member.kind.struct_name() {
+            if member_struct == target {
+                return true;
+            }
+            if visited.contains(&member_struct) {
+                continue;
+            }
+            visited.push(member_struct);
+            if let Some(member_struct) = structs.iter().find(|s| s.name == member_struct) {
+                if struct_contains(member_struct, target, structs, visited) {
+                    return true;
+                }
+            }
+        }
+    }
+    false
+}
+
+fn validate_structs(errors: &mut Vec<ValidationError>, structs: &[ShaderStruct]) {
+    for (index, shader_struct) in structs.iter().enumerate() {
+        if structs[..index]
+            .iter()
+            .any(|other| other.name == shader_struct.name)
+        {
+            errors.push(ValidationError::DuplicateStruct {
+                name: shader_struct.name.clone(),
+            });
+        }
+        if shader_struct.members.is_empty() {
+            errors.push(ValidationError::EmptyStruct {
+                name: shader_struct.name.clone(),
+            });
+        }
+        for member in &shader_struct.members {
+            validate_uniform_declaration(errors, member, structs);
+        }
+        if struct_contains(shader_struct, &shader_struct.name, structs, &mut Vec::new()) {
+            errors.push(ValidationError::RecursiveStruct {
+                name: shader_struct.name.clone(),
+            });
+        }
+    }
+}
+
+fn push_collection_conditions<'a>(conditions: &mut Vec<&'a str>, collection: &'a UniformCollection) {
+    for uniform in &collection.uniforms {
+        conditions.extend(uniform.when.as_deref());
+    }
+    for block in &collection.uniform_blocks {
+        conditions.extend(block.when.as_deref());
+        for uniform in &block.uniforms {
+            conditions.extend(uniform.when.as_deref());
+        }
+    }
+}
+
+fn push_stage_conditions<'a>(conditions: &mut Vec<&'a str>, stage: &'a ShaderStage) {
+    push_collection_conditions(conditions, &stage.uniform_collection);
+    for import in &stage.import_fn {
+        if let crate::FunctionImport::Conditional { when, .. } = import {
+            conditions.push(when);
+        }
+    }
+}
+
+impl ShaderSource {
+    /// Checks the interface between the vertex and fragment stages without compiling anything.
+    /// Returns every problem found instead of stopping at the first one.
+    /// Declarations with a `when` condition are checked as they appear in the default variant.
+    pub fn validate(&self) -> Result<(), Vec<ValidationError>> {
+        let mut errors = match self.validate_keywords() {
+            Ok(()) => Vec::new(),
+            Err(errors) => errors,
+        };
+        match self.specialize(&self.default_variant()) {
+            Ok(specialized) => {
+                if let Err(interface_errors) = specialized.validate_interface() {
+                    errors.extend(interface_errors);
+                }
+            }
+            Err(error) => errors.push(ValidationError::Variant(error)),
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Checks the keyword declarations and every `when` condition in the source
+    pub fn validate_keywords(&self) -> Result<(), Vec<ValidationError>> {
+        let mut errors = Vec::new();
+        for (index, keyword) in self.keywords.iter().enumerate() {
+            if self.keywords[..index]
+                .iter()
+                .any(|other| other.name() == keyword.name())
+            {
+                errors.push(ValidationError::DuplicateKeyword {
+                    name: keyword.name().to_string(),
+                });
+            }
+            if let ShaderKeyword::Int {
+                name,
+                values,
+                default,
+            } = keyword
+            {
+                let default_is_valid = match default {
+                    Some(value) => values.contains(value),
+                    None => true,
+                };
+                if values.is_empty() || !default_is_valid {
+                    errors.push(ValidationError::InvalidKeyword { name: name.clone() });
+                }
+            }
+        }
+
+        let mut conditions = Vec::new();
+        for vary in &self.varyings {
+            conditions.extend(vary.when.as_deref());
+        }
+        for shader_struct in &self.structs {
+            for member in &shader_struct.members {
+                conditions.extend(member.when.as_deref());
+            }
+        }
+        push_collection_conditions(&mut conditions, &self.common_uniforms);
+        push_stage_conditions(&mut conditions, &self.vertex_shader);
+        if let Some(fragment_shader) = &self.fragment_shader {
+            push_stage_conditions(&mut conditions, fragment_shader);
+        }
+        for condition in conditions {
+            match parse_condition(condition) {
+                Ok((name, _, _)) => {
+                    if !self.keywords.iter().any(|keyword| keyword.name() == name) {
+                        errors.push(ValidationError::Variant(VariantError::UnknownKeyword {
+                            name: name.to_string(),
+                        }));
+                    }
+                }
+                Err(error) => errors.push(ValidationError::Variant(error)),
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Interface checks on a source whose conditional declarations were already resolved
+    pub(crate) fn validate_interface(&self) -> Result<(), Vec<ValidationError>> {
+        let mut errors = Vec::new();
+
+        validate_attributes(
+            &mut errors,
+            &self.vertex_shader.attributes,
+            ShaderStageKind::Vertex,
+        );
+        validate_precision(&mut errors, &self.vertex_shader, ShaderStageKind::Vertex);
+
+        for vary in &self.varyings {
+            if vary.kind == WebGLDataType::Void || vary.kind.is_bool() || vary.kind.is_opaque() {
+                errors.push(ValidationError::InvalidVaryingType {
+                    name: vary.name.clone(),
+                    kind: vary.kind,
+                });
+            } else if vary.kind.is_integer()
+                && !matches!(vary.interp, Some(InterpolationQualifier::Flat))
+            {
+                errors.push(ValidationError::IntegerVaryingNotFlat {
+                    name: vary.name.clone(),
+                    kind: vary.kind,
+                });
+            }
+        }
+
+        validate_transform_feedback(&mut errors, self);
+        validate_structs(&mut errors, &self.structs);
+        validate_uniform_types(&mut errors, &self.common_uniforms, &self.structs);
+        validate_uniform_types(
+            &mut errors,
+            &self.vertex_shader.uniform_collection,
+            &self.structs,
+        );
+
+        validate_stage_scope(
+            &mut errors,
+            self,
+            &self.vertex_shader,
+            ShaderStageKind::Vertex,
+        );
+
+        if let Some(fragment_shader) = &self.fragment_shader {
+            validate_attributes(
+                &mut errors,
+                &fragment_shader.attributes,
+                ShaderStageKind::Fragment,
+            );
+            validate_precision(&mut errors, fragment_shader, ShaderStageKind::Fragment);
+            validate_uniform_types(
+                &mut errors,
+                &fragment_shader.uniform_collection,
+                &self.structs,
+            );
+            validate_stage_scope(&mut errors, self, fragment_shader, ShaderStageKind::Fragment);
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{shader_source_from_str, ShaderUniformBlock, ShaderVarying};
+
+    fn base_source() -> ShaderSource {
+        shader_source_from_str(
+            r#"
+            name = "validation"
+            varyings = [{ interp = "Smooth", kind = "Vec2", name = "uv" }]
+            imported_functions = []
+
+            [common_uniforms]
+            uniforms = [{ kind = "Mat4", name = "view_projection" }]
+            uniform_blocks = []
+
+            [vertex_shader]
+            import_fn = []
+            main_fn = ""
+            attributes = [
+                { layout_loc = 0, kind = "Vec3", name = "position" },
+                { layout_loc = 1, kind = "Vec2", name = "texcoord" },
+            ]
+            [vertex_shader.uniform_collection]
+            uniforms = []
+            uniform_blocks = []
+
+            [fragment_shader]
+            import_fn = []
+            main_fn = ""
+            attributes = [{ layout_loc = 0, kind = "Vec4", name = "frag_color" }]
+            [fragment_shader.uniform_collection]
+            uniforms = [{ kind = "Sampler2D", name = "albedo" }]
+            uniform_blocks = []
+            "#,
+        )
+        .unwrap()
+    }
+
+    fn uniform(kind: WebGLDataType, name: &str) -> ShaderUniform {
+        ShaderUniform {
+            array_length: None,
+            kind: kind.into(),
+            name: name.into(),
+            when: None,
+        }
+    }
+
+    fn fragment_shader(source: &mut ShaderSource) -> &mut ShaderStage {
+        source.fragment_shader.as_mut().unwrap()
+    }
+
+    #[test]
+    fn valid_source_passes() {
+        assert_eq!(base_source().validate(), Ok(()));
+    }
+
+    #[test]
+    fn duplicate_layout_location() {
+        let mut source = base_source();
+        source.vertex_shader.attributes[1].layout_loc = 0;
+        assert_eq!(
+            source.validate(),
+            Err(vec![ValidationError::DuplicateAttributeLocation {
+                stage: ShaderStageKind::Vertex,
+                location: 0,
+                first: "position".into(),
+                second: "texcoord".into(),
+            }])
+        );
+    }
+
+    #[test]
+    fn integer_varying_must_be_flat() {
+        let mut source = base_source();
+        source.varyings.push(ShaderVarying {
+            interp: None,
+            kind: WebGLDataType::UVec2,
+            name: "instance_id".into(),
+            when: None,
+        });
+        assert_eq!(
+            source.validate(),
+            Err(vec![ValidationError::IntegerVaryingNotFlat {
+                name: "instance_id".into(),
+                kind: WebGLDataType::UVec2,
+            }])
+        );
+
+        source.varyings[1].interp = Some(InterpolationQualifier::Flat);
+        assert_eq!(source.validate(), Ok(()));
+    }
+
+    #[test]
+    fn fragment_output_types() {
+        // Integer and float outputs are both valid, for integer and normalized attachments
+        let mut source = base_source();
+        let outputs = &mut fragment_shader(&mut source).attributes;
+        outputs.push(ShaderAttribute::frag_output(1, WebGLDataType::UVec2, "id"));
+        outputs.push(ShaderAttribute::frag_output(
+            2,
+            WebGLDataType::IVec4,
+            "bits",
+        ));
+        assert_eq!(source.validate(), Ok(()));
+
+        // Outputs cannot be booleans, matrices or opaque types
+        fragment_shader(&mut source).attributes[1].kind = WebGLDataType::BVec2;
+        fragment_shader(&mut source).attributes[2].kind = WebGLDataType::Mat4;
+        assert_eq!(
+            source.validate(),
+            Err(vec![
+                ValidationError::InvalidAttributeType {
+                    stage: ShaderStageKind::Fragment,
+                    name: "id".into(),
+                    kind: WebGLDataType::BVec2,
+                },
+                ValidationError::InvalidAttributeType {
+                    stage: ShaderStageKind::Fragment,
+                    name: "bits".into(),
+                    kind: WebGLDataType::Mat4,
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn name_clashes_between_uniforms_varyings_and_attributes() {
+        let mut source = base_source();
+        source
+            .vertex_shader
+            .uniform_collection
+            .uniforms
+            .push(uniform(WebGLDataType::Vec3, "position"));
+        fragment_shader(&mut source)
+            .uniform_collection
+            .uniforms
+            .push(uniform(WebGLDataType::Vec2, "uv"));
+        fragment_shader(&mut source).attributes[0].name = "view_projection".into();
+        assert_eq!(
+            source.validate(),
+            Err(vec![
+                ValidationError::NameClash {
+                    stage: ShaderStageKind::Vertex,
+                    name: "position".into(),
+                    first: DeclarationKind::Uniform,
+                    second: DeclarationKind::Attribute,
+                },
+                ValidationError::NameClash {
+                    stage: ShaderStageKind::Fragment,
+                    name: "uv".into(),
+                    first: DeclarationKind::Uniform,
+                    second: DeclarationKind::Varying,
+                },
+                ValidationError::NameClash {
+                    stage: ShaderStageKind::Fragment,
+                    name: "view_projection".into(),
+                    first: DeclarationKind::Uniform,
+                    second: DeclarationKind::Attribute,
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn uniform_block_and_struct_names_clash() {
+        let mut source = base_source();
+        let light = ShaderStruct {
+            name: "Light".into(),
+            members: vec![uniform(WebGLDataType::Vec3, "color")],
+        };
+        source.structs = vec![light.clone(), light];
+        source
+            .vertex_shader
+            .uniform_collection
+            .uniform_blocks
+            .push(ShaderUniformBlock {
+                binding_number: 0,
+                name: "Light".into(),
+                uniforms: vec![uniform(WebGLDataType::Vec4, "light_color")],
+                when: None,
+            });
+        let fragment_collection = &mut fragment_shader(&mut source).uniform_collection;
+        fragment_collection
+            .uniforms
+            .push(uniform(WebGLDataType::Float, "Material"));
+        fragment_collection.uniform_blocks.push(ShaderUniformBlock {
+            binding_number: 1,
+            name: "Material".into(),
+            uniforms: vec![uniform(WebGLDataType::Vec4, "base_color")],
+            when: None,
+        });
+        assert_eq!(
+            source.validate(),
+            Err(vec![
+                ValidationError::DuplicateStruct {
+                    name: "Light".into()
+                },
+                ValidationError::NameClash {
+                    stage: ShaderStageKind::Vertex,
+                    name: "Light".into(),
+                    first: DeclarationKind::Struct,
+                    second: DeclarationKind::UniformBlock,
+                },
+                ValidationError::NameClash {
+                    stage: ShaderStageKind::Fragment,
+                    name: "Material".into(),
+                    first: DeclarationKind::Uniform,
+                    second: DeclarationKind::UniformBlock,
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn every_error_is_returned() {
+        let mut source = base_source();
+        source.keywords.push(ShaderKeyword::Int {
+            name: "LIGHTS".into(),
+            values: vec![1, 2],
+            default: Some(4),
+        });
+        source.vertex_shader.attributes[1].layout_loc = 0;
+        source.varyings[0].kind = WebGLDataType::Int;
+        source.varyings[0].interp = None;
+        source.common_uniforms.uniforms[0].when = Some("SHADOWS".into());
+        fragment_shader(&mut source).attributes[0].kind = WebGLDataType::Bool;
+
+        let errors = source.validate().unwrap_err();
+        assert_eq!(
+            errors,
+            [
+                ValidationError::InvalidKeyword {
+                    name: "LIGHTS".into()
+                },
+                ValidationError::Variant(VariantError::UnknownKeyword {
+                    name: "SHADOWS".into()
+                }),
+                ValidationError::DuplicateAttributeLocation {
+                    stage: ShaderStageKind::Vertex,
+                    location: 0,
+                    first: "position".into(),
+                    second: "texcoord".into(),
+                },
+                ValidationError::IntegerVaryingNotFlat {
+                    name: "uv".into(),
+                    kind: WebGLDataType::Int,
+                },
+                ValidationError::InvalidAttributeType {
+                    stage: ShaderStageKind::Fragment,
+                    name: "frag_color".into(),
+                    kind: WebGLDataType::Bool,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,536 @@
+use std::{collections::BTreeMap, fmt};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{FunctionImport, ShaderSource, ShaderStage, ShaderStruct, UniformCollection};
+
+/// A keyword that selects a variant of a `ShaderSource`. Each keyword becomes a `#define`
+/// in both stages: an enabled `Bool` keyword is defined as `1` (a disabled one is not defined,
+/// use `#ifdef`), an `Int` keyword is always defined with its value.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum ShaderKeyword {
+    Bool {
+        name: String,
+        #[serde(default)]
+        default: bool,
+    },
+    Int {
+        name: String,
+        values: Vec<i32>,
+        default: Option<i32>,
+    },
+}
+
+impl ShaderKeyword {
+    pub fn name(&self) -> &str {
+        match self {
+            ShaderKeyword::Bool { name, .. } | ShaderKeyword::Int { name, .. } => name,
+        }
+    }
+
+    pub fn default_value(&self) -> KeywordValue {
+        match self {
+            ShaderKeyword::Bool { default, .. } => KeywordValue::Bool(*default),
+            ShaderKeyword::Int {
+                values, default, ..
+            } => KeywordValue::Int(default.unwrap_or_else(|| values.first().copied().unwrap_or(0))),
+        }
+    }
+
+    /// Every value this keyword can take, in declaration order
+    pub fn values(&self) -> Vec<KeywordValue> {
+        match self {
+            ShaderKeyword::Bool { .. } => vec![KeywordValue::Bool(false), KeywordValue::Bool(true)],
+            ShaderKeyword::Int { values, .. } => {
+                values.iter().map(|value| KeywordValue::Int(*value)).collect()
+            }
+        }
+    }
+
+    fn accepts(&self, value: KeywordValue) -> bool {
+        match (self, value) {
+            (ShaderKeyword::Bool { .. }, KeywordValue::Bool(_)) => true,
+            (ShaderKeyword::Int { values, .. }, KeywordValue::Int(value)) => values.contains(&value),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeywordValue {
+    Bool(bool),
+    Int(i32),
+}
+
+impl KeywordValue {
+    fn as_int(&self) -> i32 {
+        match self {
+            KeywordValue::Bool(value) => *value as i32,
+            KeywordValue::Int(value) => *value,
+        }
+    }
+}
+
+impl fmt::Display for KeywordValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_int())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VariantError {
+    UnknownKeyword { name: String },
+    InvalidValue { name: String, value: KeywordValue },
+    InvalidCondition { condition: String },
+}
+
+impl fmt::Display for VariantError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VariantError::UnknownKeyword { name } => {
+                write!(f, "keyword '{}' is not declared in the shader source", name)
+            }
+            VariantError::InvalidValue { name, value } => {
+                write!(f, "keyword '{}' cannot take the value {}", name, value)
+            }
+            VariantError::InvalidCondition { condition } => write!(
+                f,
+                "condition '{}' should look like 'NAME', '!NAME' or 'NAME <op> <int>'",
+                condition
+            ),
+        }
+    }
+}
+
+/// A selection of keyword values. Keywords that are not set take their default value
+/// when the variant is resolved against a `ShaderSource`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct ShaderVariant {
+    values: BTreeMap<String, KeywordValue>,
+}
+
+impl ShaderVariant {
+    pub fn new() -> Self {
+        Self {
+            values: BTreeMap::new(),
+        }
+    }
+
+    pub fn set_bool(&mut self, name: &str, value: bool) {
+        self.values.insert(name.to_string(), KeywordValue::Bool(value));
+    }
+
+    pub fn set_int(&mut self, name: &str, value: i32) {
+        self.values.insert(name.to_string(), KeywordValue::Int(value));
+    }
+
+    pub fn get(&self, name: &str) -> Option<KeywordValue> {
+        self.values.get(name).copied()
+    }
+
+    /// Stable identifier of the variant, keywords are sorted by name: `ALPHA_TEST=0;BONES=4`
+    pub fn key(&self) -> String {
+        self.values
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<String>>()
+            .join(";")
+    }
+
+    /// The `#define` lines for this variant
+    pub fn defines(&self) -> String {
+        let mut defines = String::new();
+        for (name, value) in &self.values {
+            match value {
+                KeywordValue::Bool(false) => {}
+                KeywordValue::Bool(true) => defines.push_str(&format!("#define {} 1\n", name)),
+                KeywordValue::Int(value) => {
+                    defines.push_str(&format!("#define {} {}\n", name, value))
+                }
+            }
+        }
+        defines
+    }
+
+    /// Evaluates a `when` condition: `NAME`, `!NAME` or `NAME <op> <int>` where `<op>` is one of
+    /// `==`, `!=`, `<`, `<=`, `>`, `>=`. Keywords missing from the variant count as 0.
+    pub fn matches(&self, condition: &str) -> Result<bool, VariantError> {
+        let (name, op, rhs) = parse_condition(condition)?;
+        let lhs = self.get(name).map(|value| value.as_int()).unwrap_or(0);
+        Ok(match op {
+            "==" => lhs == rhs,
+            "!=" => lhs != rhs,
+            "<=" => lhs <= rhs,
+            ">=" => lhs >= rhs,
+            "<" => lhs < rhs,
+            _ => lhs > rhs,
+        })
+    }
+
+    fn matches_optional(&self, condition: &Option<String>) -> Result<bool, VariantError> {
+        match condition {
+            Some(condition) => self.matches(condition),
+            None => Ok(true),
+        }
+    }
+}
+
+/// Splits a condition into the keyword name, the comparison operator and the integer operand
+pub(crate) fn parse_condition(condition: &str) -> Result<(&str, &'static str, i32), VariantError> {
+    let invalid = || VariantError::InvalidCondition {
+        condition: condition.to_string(),
+    };
+    let condition_str = condition.trim();
+    let (name, op, rhs) = if let Some(name) = condition_str.strip_prefix('!') {
+        (name.trim(), "==", 0)
+    } else {
+        let mut parsed = None;
+        for op in ["==", "!=", "<=", ">=", "<", ">"] {
+            if let Some((name, value)) = condition_str.split_once(op) {
+                let rhs: i32 = value.trim().parse().map_err(|_| invalid())?;
+                parsed = Some((name.trim(), op, rhs));
+                break;
+            }
+        }
+        parsed.unwrap_or((condition_str, "!=", 0))
+    };
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(invalid());
+    }
+    Ok((name, op, rhs))
+}
+
+fn specialize_collection(
+    collection: &UniformCollection,
+    variant: &ShaderVariant,
+) -> Result<UniformCollection, VariantError> {
+    let mut specialized = UniformCollection::new();
+    for uniform in &collection.uniforms {
+        if variant.matches_optional(&uniform.when)? {
+            specialized.uniforms.push(uniform.clone());
+        }
+    }
+    for block in &collection.uniform_blocks {
+        if variant.matches_optional(&block.when)? {
+            let mut block = block.clone();
+            let mut uniforms = Vec::new();
+            for uniform in block.uniforms {
+                if variant.matches_optional(&uniform.when)? {
+                    uniforms.push(uniform);
+                }
+            }
+            block.uniforms = uniforms;
+            specialized.uniform_blocks.push(block);
+        }
+    }
+    Ok(specialized)
+}
+
+fn specialize_struct(
+    shader_struct: &ShaderStruct,
+    variant: &ShaderVariant,
+) -> Result<ShaderStruct, VariantError> {
+    let mut members = Vec::new();
+    for member in &shader_struct.members {
+        if variant.matches_optional(&member.when)? {
+            members.push(member.clone());
+        }
+    }
+    Ok(ShaderStruct {
+        name: shader_struct.name.clone(),
+        members,
+    })
+}
+
+fn specialize_stage(
+    stage: &ShaderStage,
+    variant: &ShaderVariant,
+) -> Result<ShaderStage, VariantError> {
+    let mut import_fn = Vec::new();
+    for import in &stage.import_fn {
+        if variant.matches_optional(&import.condition())? {
+            import_fn.push(FunctionImport::Name(import.name().to_string()));
+        }
+    }
+    Ok(ShaderStage {
+        version: stage.version.clone(),
+        precision: stage.precision.clone(),
+        header: stage.header.clone(),
+        import_fn,
+        main_fn: stage.main_fn.clone(),
+        attributes: stage.attributes.clone(),
+        uniform_collection: specialize_collection(&stage.uniform_collection, variant)?,
+    })
+}
+
+impl ShaderSource {
+    pub fn default_variant(&self) -> ShaderVariant {
+        let mut variant = ShaderVariant::new();
+        for keyword in &self.keywords {
+            variant
+                .values
+                .insert(keyword.name().to_string(), keyword.default_value());
+        }
+        variant
+    }
+
+    /// Fills the keywords missing from `selection` with their defaults and checks that every
+    /// selected keyword is declared and takes an allowed value
+    pub fn resolve_variant(&self, selection: &ShaderVariant) -> Result<ShaderVariant, VariantError> {
+        for (name, value) in &selection.values {
+            match self.keywords.iter().find(|keyword| keyword.name() == name) {
+                Some(keyword) => {
+                    if !keyword.accepts(*value) {
+                        return Err(VariantError::InvalidValue {
+                            name: name.clone(),
+                            value: *value,
+                        });
+                    }
+                }
+                None => {
+                    return Err(VariantError::UnknownKeyword { name: name.clone() });
+                }
+            }
+        }
+        let mut variant = self.default_variant();
+        variant.values.extend(selection.values.clone());
+        Ok(variant)
+    }
+
+    /// Every permutation of the declared keywords
+    pub fn variants(&self) -> Vec<ShaderVariant> {
+        let mut variants = vec![ShaderVariant::new()];
+        for keyword in &self.keywords {
+            let mut next = Vec::new();
+            for variant in &variants {
+                for value in keyword.values() {
+                    let mut variant = variant.clone();
+                    variant.values.insert(keyword.name().to_string(), value);
+                    next.push(variant);
+                }
+            }
+            variants = next;
+        }
+        variants
+    }
+
+    /// Returns a copy of the source without the declarations whose `when` condition is false for
+    /// the (already resolved) `variant`
+    pub fn specialize(&self, variant: &ShaderVariant) -> Result<ShaderSource, VariantError> {
+        let mut varyings = Vec::new();
+        for vary in &self.varyings {
+            if variant.matches_optional(&vary.when)? {
+                varyings.push(vary.clone());
+            }
+        }
+        let mut structs = Vec::new();
+        for shader_struct in &self.structs {
+            structs.push(specialize_struct(shader_struct, variant)?);
+        }
+        Ok(ShaderSource {
+            name: self.name.clone(),
+            keywords: self.keywords.clone(),
+            structs,
+            varyings,
+            common_uniforms: specialize_collection(&self.common_uniforms, variant)?,
+            imported_functions: self.imported_functions.clone(),
+            local_import: self.local_import.clone(),
+            vertex_shader: specialize_stage(&self.vertex_shader, variant)?,
+            fragment_shader: match &self.fragment_shader {
+                Some(stage) => Some(specialize_stage(stage, variant)?),
+                None => None,
+            },
+            transform_feedback: self.transform_feedback.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shader_source_from_str;
+
+    fn keyword_source() -> ShaderSource {
+        shader_source_from_str(
+            r#"
+            name = "variants"
+            keywords = [
+                { type = "Bool", name = "SKINNED" },
+                { type = "Int", name = "LIGHTS", values = [1, 2, 4], default = 2 },
+            ]
+            varyings = [
+                { kind = "Vec2", name = "uv" },
+                { kind = "Vec4", name = "weights", when = "SKINNED" },
+            ]
+            imported_functions = []
+
+            [common_uniforms]
+            uniforms = [
+                { kind = "Mat4", name = "view_projection" },
+                { kind = "Vec3", name = "light_directions", array_length = 4, when = "LIGHTS >= 4" },
+            ]
+            uniform_blocks = []
+
+            [vertex_shader]
+            import_fn = ["project", { name = "apply_skinning", when = "SKINNED" }]
+            main_fn = ""
+            attributes = []
+            [vertex_shader.uniform_collection]
+            uniforms = [{ kind = "Mat4", name = "bones", array_length = 32, when = "SKINNED" }]
+            uniform_blocks = []
+            "#,
+        )
+        .unwrap()
+    }
+
+    fn varying_names(source: &ShaderSource) -> Vec<&str> {
+        source
+            .varyings
+            .iter()
+            .map(|vary| vary.name.as_str())
+            .collect()
+    }
+
+    fn import_names(stage: &ShaderStage) -> Vec<&str> {
+        stage.import_fn.iter().map(|import| import.name()).collect()
+    }
+
+    #[test]
+    fn key_does_not_depend_on_insertion_order() {
+        let mut first = ShaderVariant::new();
+        first.set_int("LIGHTS", 4);
+        first.set_bool("SKINNED", true);
+        first.set_bool("ALPHA_TEST", false);
+        let mut second = ShaderVariant::new();
+        second.set_bool("ALPHA_TEST", false);
+        second.set_bool("SKINNED", true);
+        second.set_int("LIGHTS", 4);
+
+        assert_eq!(first.key(), "ALPHA_TEST=0;LIGHTS=4;SKINNED=1");
+        assert_eq!(first.key(), second.key());
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn defines_skip_disabled_bool_keywords() {
+        let mut variant = ShaderVariant::new();
+        variant.set_bool("SKINNED", true);
+        variant.set_bool("ALPHA_TEST", false);
+        variant.set_int("LIGHTS", 0);
+        assert_eq!(variant.defines(), "#define LIGHTS 0\n#define SKINNED 1\n");
+    }
+
+    #[test]
+    fn conditions() {
+        let mut variant = ShaderVariant::new();
+        variant.set_bool("SKINNED", true);
+        variant.set_int("LIGHTS", 2);
+        assert_eq!(variant.matches("SKINNED"), Ok(true));
+        assert_eq!(variant.matches("!SKINNED"), Ok(false));
+        assert_eq!(variant.matches(" LIGHTS >= 2 "), Ok(true));
+        assert_eq!(variant.matches("LIGHTS<2"), Ok(false));
+        assert_eq!(variant.matches("LIGHTS != 4"), Ok(true));
+        // Keywords missing from the variant count as 0
+        assert_eq!(variant.matches("ALPHA_TEST"), Ok(false));
+        assert_eq!(variant.matches("!ALPHA_TEST"), Ok(true));
+        assert_eq!(
+            variant.matches("LIGHTS >= many"),
+            Err(VariantError::InvalidCondition {
+                condition: "LIGHTS >= many".into()
+            })
+        );
+        assert_eq!(
+            variant.matches("SKINNED && LIGHTS"),
+            Err(VariantError::InvalidCondition {
+                condition: "SKINNED && LIGHTS".into()
+            })
+        );
+    }
+
+    #[test]
+    fn specialize_filters_declarations() {
+        let source = keyword_source();
+
+        let default = source.resolve_variant(&ShaderVariant::new()).unwrap();
+        assert_eq!(default.key(), "LIGHTS=2;SKINNED=0");
+        let specialized = source.specialize(&default).unwrap();
+        assert_eq!(varying_names(&specialized), ["uv"]);
+        assert_eq!(specialized.common_uniforms.uniforms.len(), 1);
+        let vertex_uniforms = &specialized.vertex_shader.uniform_collection.uniforms;
+        assert!(vertex_uniforms.is_empty());
+        assert_eq!(import_names(&specialized.vertex_shader), ["project"]);
+
+        let mut selection = ShaderVariant::new();
+        selection.set_bool("SKINNED", true);
+        selection.set_int("LIGHTS", 4);
+        let variant = source.resolve_variant(&selection).unwrap();
+        let specialized = source.specialize(&variant).unwrap();
+        assert_eq!(varying_names(&specialized), ["uv", "weights"]);
+        let vertex_uniforms = &specialized.vertex_shader.uniform_collection.uniforms;
+        assert_eq!(vertex_uniforms[0].name, "bones");
+        assert_eq!(
+            specialized.common_uniforms.uniforms[1].name,
+            "light_directions"
+        );
+        assert_eq!(
+            import_names(&specialized.vertex_shader),
+            ["project", "apply_skinning"]
+        );
+    }
+
+    #[test]
+    fn variants_cover_every_permutation() {
+        let keys: Vec<String> = keyword_source()
+            .variants()
+            .iter()
+            .map(|variant| variant.key())
+            .collect();
+        assert_eq!(
+            keys,
+            [
+                "LIGHTS=1;SKINNED=0",
+                "LIGHTS=2;SKINNED=0",
+                "LIGHTS=4;SKINNED=0",
+                "LIGHTS=1;SKINNED=1",
+                "LIGHTS=2;SKINNED=1",
+                "LIGHTS=4;SKINNED=1",
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_keyword_and_out_of_range_value() {
+        let source = keyword_source();
+
+        let mut selection = ShaderVariant::new();
+        selection.set_bool("SHADOWS", true);
+        assert_eq!(
+            source.resolve_variant(&selection),
+            Err(VariantError::UnknownKeyword {
+                name: "SHADOWS".into()
+            })
+        );
+
+        let mut selection = ShaderVariant::new();
+        selection.set_int("LIGHTS", 3);
+        assert_eq!(
+            source.resolve_variant(&selection),
+            Err(VariantError::InvalidValue {
+                name: "LIGHTS".into(),
+                value: KeywordValue::Int(3),
+            })
+        );
+
+        // A bool value for an int keyword is out of range as well
+        let mut selection = ShaderVariant::new();
+        selection.set_bool("LIGHTS", true);
+        assert_eq!(
+            source.resolve_variant(&selection),
+            Err(VariantError::InvalidValue {
+                name: "LIGHTS".into(),
+                value: KeywordValue::Bool(true),
+            })
+        );
+    }
+}
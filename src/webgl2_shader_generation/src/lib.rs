@@ -1,31 +1,33 @@
 use proc_macro::TokenStream;
 use quote::quote;
 use std::{
-    collections::HashMap,
     fs,
     path::{self, PathBuf},
 };
-use syn::{parse_macro_input, LitStr};
+use syn::{
+    parse::{Parse, ParseStream},
+    parse_macro_input, DeriveInput, LitStr, Token,
+};
 use webgl2_shader_definition::*;
 
-// Checks that the `import_file_path` is valid; if so, add the file to `promise_imports` and `function_imports`.
-fn push_file_function_imports(
-    function_imports: &mut HashMap<String, FunctionImports>,
-    promise_imports: &mut Vec<(String, String)>,
-    import_file_path: PathBuf,
-    path: String,
-    fn_name: String,
-) {
+mod uniform_block;
+
+// Reads the function file at `import_file_path`. The format is the one of the extension,
+// `format` (the explicit format of the macro) for files without a known extension, YAML if
+// neither gives one.
+fn read_function_file(
+    import_file_path: &path::Path,
+    format: Option<ShaderFormat>,
+) -> Vec<FunctionDefinition> {
+    let path = import_file_path.to_string_lossy();
     match fs::read_to_string(import_file_path) {
-        Ok(file_string) => match serde_yaml::from_str(&file_string) {
-            Ok(fn_imports) => {
-                function_imports.insert(path.clone(), fn_imports);
-                promise_imports.push((path.clone(), fn_name));
-            }
-            Err(err) => {
-                panic!("Could not deserialize imported function text {}", err);
+        Ok(file_string) => {
+            let format = ShaderFormat::from_path(&path).or(format);
+            match load_function_imports(&path, &file_string, format) {
+                Ok(imports) => imports.function_definitions,
+                Err(err) => panic!("Could not deserialize imported function text {}", err),
             }
-        },
+        }
         Err(err) => {
             panic!(
                 "Could not read imported file |path: {}| |error: {}|",
@@ -35,65 +37,109 @@ fn push_file_function_imports(
     }
 }
 
+// Rewrites the paths of the `ImportFn` definitions that name a file to the canonical path of the
+// file, relative paths start at `dir` (the directory of the file the definitions come from).
+// Built-in imports are left as they are. The canonical paths are returned.
+fn canonicalize_import_paths(
+    functions: &mut [FunctionDefinition],
+    dir: &path::Path,
+) -> Vec<PathBuf> {
+    let mut file_paths = Vec::new();
+    for function in functions {
+        if let FunctionDefinitionType::ImportFn { path } = &mut function.definition {
+            if path.starts_with(shader_functions::BUILTIN_PREFIX) {
+                continue;
+            }
+            let import_file_path = dir.join(&*path);
+            let file_path = fs::canonicalize(&import_file_path).unwrap_or_else(|err| {
+                panic!(
+                    "Could not read imported file |path: {}| |error: {}|",
+                    import_file_path.display(),
+                    err
+                )
+            });
+            *path = file_path.to_string_lossy().into_owned();
+            file_paths.push(file_path);
+        }
+    }
+    file_paths
+}
+
 // Takes an array of Function Definitions and the path of the shader and transforms them
 // into an array of Function Definitions with type InlineFn. For the InlineFn functions in the
-// array is a simple copy, for the ImportFn, the necessary file is read.
+// array is a simple copy, for the ImportFn, the necessary file is read, along with the files it
+// imports from. Import paths are relative to the file that contains them and every file is read
+// once, the library is keyed by canonical path. `builtin:` paths are resolved with the
+// `shader_functions` of the definition crate. The function files that were read are returned to
+// be tracked.
 fn generate_imported_functions(
-    source_imported_fn: &Vec<FunctionDefinition>,
+    source_imported_fn: &[FunctionDefinition],
     file_dir: &path::Path,
-) -> Vec<FunctionDefinition> {
-    let mut imported_fn = Vec::<FunctionDefinition>::new();
-    let mut function_imports = HashMap::<String, FunctionImports>::new();
-    let mut promise_imports = Vec::<(String, String)>::new();
-
-    for import_fn in source_imported_fn {
-        match &import_fn.definition {
-            FunctionDefinitionType::InlineFn { .. } => {
-                imported_fn.push(import_fn.clone());
-            }
-            FunctionDefinitionType::ImportFn { path } => {
-                let import_file_path = file_dir.join(path.clone());
-                if !function_imports.contains_key(path) {
-                    push_file_function_imports(
-                        &mut function_imports,
-                        &mut promise_imports,
-                        import_file_path,
-                        path.clone(),
-                        import_fn.name.clone(),
-                    );
-                } else {
-                    promise_imports.push((path.clone(), import_fn.name.clone()));
-                }
-            }
+    format: Option<ShaderFormat>,
+) -> (Vec<FunctionDefinition>, Vec<PathBuf>) {
+    let mut library = MemoryFunctionLibrary::new();
+    let mut read_files = Vec::new();
+    let mut source_imported_fn = source_imported_fn.to_vec();
+    let mut pending_files = canonicalize_import_paths(&mut source_imported_fn, file_dir);
+    while let Some(file_path) = pending_files.pop() {
+        let key = file_path.to_string_lossy().into_owned();
+        if library.contains(&key) {
+            continue;
         }
+        let mut functions = read_function_file(&file_path, format);
+        let import_dir = file_path.parent().unwrap_or(file_dir);
+        pending_files.extend(canonicalize_import_paths(&mut functions, import_dir));
+        library.insert(&key, functions);
+        read_files.push(file_path);
     }
 
-    for promise in promise_imports {
-        match function_imports.get(&promise.0) {
-            Some(fn_imports) => {
-                match fn_imports
-                    .function_definitions
-                    .iter()
-                    .find(|def| def.name == promise.1)
-                {
-                    Some(fn_def) => imported_fn.push(fn_def.clone()),
-                    None => {
-                        panic!("Could not find function definition inside the imported file | path: {}", promise.0);
-                    }
-                }
-            }
-            None => {
-                panic!("File was not imported | path: {}", promise.0);
-            }
+    match resolve_imported_functions(&source_imported_fn, &library) {
+        Ok(imported_fn) => (imported_fn, read_files),
+        Err(err) => panic!("Could not import functions | error: {}", err),
+    }
+}
+
+// Includes every file read by the macro in the expansion, so cargo rebuilds the crate when one of
+// them changes. A file is included once however its path is spelled.
+fn track_files(files: &[PathBuf]) -> proc_macro2::TokenStream {
+    let mut canonical_files: Vec<PathBuf> = Vec::new();
+    for file in files {
+        let file = fs::canonicalize(file).unwrap_or_else(|_| file.clone());
+        if !canonical_files.contains(&file) {
+            canonical_files.push(file);
         }
     }
+    let includes = canonical_files.iter().map(|file| {
+        let file = file.to_string_lossy().into_owned();
+        quote! {
+            const _: &[u8] = include_bytes!(#file);
+        }
+    });
+    quote! { #(#includes)* }
+}
 
-    imported_fn
+// Reads and parses the shader definition at `file_path`, the format is `format` or the one of the
+// file extension, TOML if neither gives one
+fn read_shader_source(file_path: &path::Path, format: Option<ShaderFormat>) -> ShaderSource {
+    match fs::read_to_string(file_path) {
+        Ok(file_str) => match load_shader_source(&file_path.to_string_lossy(), &file_str, format) {
+            Ok(source) => source,
+            Err(err) => {
+                panic!("Cannot deserialize shader source from file | error {}", err)
+            }
+        },
+        Err(err) => {
+            panic!("Cannot read file | error {}", err)
+        }
+    }
 }
 
-// Reads the YAML shader definition in the given path and returns the compiled String of the vertex
-// shader and the fragment shader (in that order).
-fn generate_shader_str(file_path: String) -> (String, String) {
+// Reads the shader definition in the given path and returns the compiled String of the vertex
+// shader and the fragment shader (in that order), with every file that was read.
+fn generate_shader_str(
+    file_path: String,
+    format: Option<ShaderFormat>,
+) -> ((String, String), Vec<PathBuf>) {
     let f_path = path::Path::new(&file_path);
 
     if f_path.is_file() && !f_path.is_absolute() {
@@ -102,25 +148,40 @@ fn generate_shader_str(file_path: String) -> (String, String) {
 
     let file_directory = f_path.parent().unwrap();
 
-    match fs::read_to_string(f_path) {
-        Ok(file_str) => match toml::from_str::<ShaderSource>(&file_str) {
-            Ok(source) => {
-                let imported_fn =
-                    generate_imported_functions(&source.imported_functions, file_directory);
-                let vertex_shader_code = generate_vertex_stage_str(&source, &imported_fn);
-                let fragment_shader_code = generate_fragment_stage_str(&source, &imported_fn);
-                (vertex_shader_code, fragment_shader_code)
-            }
-            Err(err) => {
-                panic!("Cannot deserialize shader source from file | error {}", err)
+    let source = read_shader_source(f_path, format);
+    let (imported_fn, mut read_files) =
+        generate_imported_functions(&source.imported_functions, file_directory, format);
+    read_files.insert(0, f_path.to_path_buf());
+    match generate_vertex_fragment_shader(&source, &imported_fn) {
+        Ok(shaders) => (shaders, read_files),
+        Err(err) => panic!("Cannot generate shader | error {}", err),
+    }
+}
+
+// Arguments of `generate_shader_from_file!`, the path and an optional format name
+struct ShaderFileInput {
+    path: LitStr,
+    format: Option<ShaderFormat>,
+}
+
+impl Parse for ShaderFileInput {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let path: LitStr = input.parse()?;
+        let mut format = None;
+        if input.parse::<Option<Token![,]>>()?.is_some() && !input.is_empty() {
+            let format_name: LitStr = input.parse()?;
+            match format_name.value().parse::<ShaderFormat>() {
+                Ok(shader_format) => format = Some(shader_format),
+                Err(err) => return Err(syn::Error::new(format_name.span(), err)),
             }
-        },
-        Err(err) => {
-            panic!("Cannot read file | error {}", err)
+            input.parse::<Option<Token![,]>>()?;
         }
+        Ok(Self { path, format })
     }
 }
 
+/// Same as `generate_shader_from_file!` with the shader definition always read as YAML, like
+/// `generate_shader_from_file!(path, "yaml")`
 #[proc_macro]
 pub fn generate_shader_from_yaml_file(input: TokenStream) -> TokenStream {
     let file_path: LitStr = parse_macro_input!(input as LitStr);
@@ -128,29 +189,169 @@ pub fn generate_shader_from_yaml_file(input: TokenStream) -> TokenStream {
     let file_path = path::Path::new(&path);
 
     let file_directory = file_path.parent().unwrap();
-    let file_str = fs::read_to_string(file_path).expect("Could not read the shader file");
-
-    let source: ShaderSource = serde_yaml::from_str(&file_str).expect("Could not deserialize");
+    let source = read_shader_source(file_path, Some(ShaderFormat::Yaml));
 
-    let imported_fn = generate_imported_functions(&source.imported_functions, file_directory);
-    let vertex_shader = generate_vertex_stage_str(&source, &imported_fn);
-    let fragment_shader = generate_fragment_stage_str(&source, &imported_fn);
+    let (imported_fn, mut read_files) = generate_imported_functions(
+        &source.imported_functions,
+        file_directory,
+        Some(ShaderFormat::Yaml),
+    );
+    read_files.insert(0, file_path.to_path_buf());
+    let (vertex_shader, fragment_shader) = generate_vertex_fragment_shader(&source, &imported_fn)
+        .unwrap_or_else(|err| panic!("Cannot generate shader | error {}", err));
 
+    let tracked_files = track_files(&read_files);
     let expanded = quote! {
-        (#vertex_shader, #fragment_shader)
+        {
+            #tracked_files
+            (#vertex_shader, #fragment_shader)
+        }
     };
     return TokenStream::from(expanded);
 }
 
+/// Generates the `(vertex, fragment)` shader strings of the shader definition at an absolute
+/// path. The format comes from the file extension (`.toml`, `.yaml` / `.yml`, `.json`, TOML for
+/// any other) or from a second argument, `generate_shader_from_file!("/shaders/water.def", "yaml")`.
+/// Imported function files use the format of their own extension, the explicit format only
+/// applies to the ones without a known extension (`lights.fn` is read as JSON with
+/// `generate_shader_from_file!("/shaders/water.def", "json")`), YAML if neither gives one.
+/// The shader file and every function file it imports are included in the expansion, editing
+/// one of them rebuilds the crate.
 #[proc_macro]
 pub fn generate_shader_from_file(input: TokenStream) -> TokenStream {
-    let file_path: LitStr = parse_macro_input!(input as LitStr);
-    let path = file_path.value();
+    let ShaderFileInput { path, format } = parse_macro_input!(input as ShaderFileInput);
+    let path = path.value();
 
-    let (vert_shader, frag_shader) = generate_shader_str(path.clone());
+    let ((vert_shader, frag_shader), read_files) = generate_shader_str(path.clone(), format);
 
+    let tracked_files = track_files(&read_files);
     let expanded = quote! {
-        (#vert_shader, #frag_shader)
+        {
+            #tracked_files
+            (#vert_shader, #frag_shader)
+        }
     };
     return TokenStream::from(expanded);
 }
+
+/// Derives `UniformBlock` for a struct of f32, i32, u32, bool and glam vector / matrix fields
+/// (or fixed size arrays of them). Fields can also be other `UniformBlock` structs, they are
+/// declared as GLSL structs named after their block, see `UniformBlock::structs`. A
+/// `{Name}Std140` struct with the std140 padding is generated next to it.
+/// `#[uniform_block(name = "...")]` renames the GLSL block and `#[uniform_block(crate = "...")]`
+/// changes the path used to reach `rust_webgl2`.
+#[proc_macro_derive(UniformBlock, attributes(uniform_block))]
+pub fn derive_uniform_block(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match uniform_block::derive_uniform_block(input) {
+        Ok(expanded) => TokenStream::from(expanded),
+        Err(err) => TokenStream::from(err.to_compile_error()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHADE_FILE: &str = "function_definitions:
+  - name: luma
+    definition:
+      type: ImportFn
+      path: ../c.yaml
+  - name: shade
+    definition:
+      type: InlineFn
+      return_type: Float
+      parameters: vec3 c
+      body: \"return luma(c) * 0.5;\"
+";
+
+    const LUMA_FILE: &str = "function_definitions:
+  - name: luma
+    definition:
+      type: InlineFn
+      return_type: Float
+      parameters: vec3 c
+      body: \"return dot(c, vec3(0.2126, 0.7152, 0.0722));\"
+";
+
+    // `a/b.yaml` imports `luma` from `../c.yaml`
+    fn function_files() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("a")).unwrap();
+        fs::write(dir.path().join("a/b.yaml"), SHADE_FILE).unwrap();
+        fs::write(dir.path().join("c.yaml"), LUMA_FILE).unwrap();
+        dir
+    }
+
+    fn import_fn(name: &str, path: &str) -> FunctionDefinition {
+        FunctionDefinition {
+            name: name.into(),
+            definition: FunctionDefinitionType::ImportFn { path: path.into() },
+        }
+    }
+
+    fn import_path(function: &FunctionDefinition) -> &str {
+        match &function.definition {
+            FunctionDefinitionType::ImportFn { path } => path,
+            FunctionDefinitionType::InlineFn { .. } => panic!("{} is not imported", function.name),
+        }
+    }
+
+    #[test]
+    fn import_paths_are_relative_to_their_directory() {
+        let dir = function_files();
+        let c = fs::canonicalize(dir.path().join("c.yaml")).unwrap();
+        let mut functions = vec![
+            import_fn("luma", "../c.yaml"),
+            import_fn("PBRLighting", "builtin:pbr"),
+        ];
+        let file_paths = canonicalize_import_paths(&mut functions, &dir.path().join("a"));
+        assert_eq!(file_paths, [c.clone()]);
+        assert_eq!(import_path(&functions[0]), c.to_string_lossy());
+        assert_eq!(import_path(&functions[1]), "builtin:pbr");
+    }
+
+    #[test]
+    fn nested_imports_read_each_file_once() {
+        let dir = function_files();
+        let source_imports = [
+            import_fn("shade", "a/b.yaml"),
+            import_fn("luma", "c.yaml"),
+            import_fn("luma", "a/../c.yaml"),
+        ];
+        let (functions, mut read_files) =
+            generate_imported_functions(&source_imports, dir.path(), None);
+        let names: Vec<&str> = functions
+            .iter()
+            .map(|function| function.name.as_str())
+            .collect();
+        assert_eq!(names, ["luma", "shade"]);
+
+        read_files.sort();
+        let mut expected = vec![
+            fs::canonicalize(dir.path().join("a/b.yaml")).unwrap(),
+            fs::canonicalize(dir.path().join("c.yaml")).unwrap(),
+        ];
+        expected.sort();
+        assert_eq!(read_files, expected);
+    }
+
+    #[test]
+    fn tracked_files_are_included_once() {
+        let dir = function_files();
+        let files = [
+            dir.path().join("c.yaml"),
+            dir.path().join("a/../c.yaml"),
+            dir.path().join("a/b.yaml"),
+            dir.path().join("./a/b.yaml"),
+        ];
+        let tracked = track_files(&files).to_string();
+        assert_eq!(tracked.matches("include_bytes").count(), 2);
+        for file in ["c.yaml", "a/b.yaml"] {
+            let file = fs::canonicalize(dir.path().join(file)).unwrap();
+            assert!(tracked.contains(&format!("{:?}", file.to_string_lossy())));
+        }
+    }
+}
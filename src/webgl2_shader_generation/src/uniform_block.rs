@@ -0,0 +1,382 @@
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::{Data, DeriveInput, Error, Expr, Fields, Lit, Meta, NestedMeta, Path, Result, Type};
+use webgl2_shader_definition::*;
+
+// Maps the type of a field to the GLSL type it is stored as, only the last path segment is used
+// so `glam::Vec3` and `Vec3` are the same type. `None` for the other types.
+fn field_data_type(ty: &Type) -> Option<WebGLDataType> {
+    let type_name = match ty {
+        Type::Path(type_path) if type_path.qself.is_none() => type_path
+            .path
+            .segments
+            .last()
+            .map(|segment| segment.ident.to_string())
+            .unwrap_or_default(),
+        _ => String::new(),
+    };
+    let kind = match type_name.as_str() {
+        "f32" => WebGLDataType::Float,
+        "i32" => WebGLDataType::Int,
+        "u32" => WebGLDataType::Uint,
+        "bool" => WebGLDataType::Bool,
+        "Vec2" => WebGLDataType::Vec2,
+        "Vec3" | "Vec3A" => WebGLDataType::Vec3,
+        "Vec4" => WebGLDataType::Vec4,
+        "IVec2" => WebGLDataType::IVec2,
+        "IVec3" => WebGLDataType::IVec3,
+        "IVec4" => WebGLDataType::IVec4,
+        "UVec2" => WebGLDataType::UVec2,
+        "UVec3" => WebGLDataType::UVec3,
+        "UVec4" => WebGLDataType::UVec4,
+        "BVec2" => WebGLDataType::BVec2,
+        "BVec3" => WebGLDataType::BVec3,
+        "BVec4" => WebGLDataType::BVec4,
+        "Mat2" => WebGLDataType::Mat2,
+        "Mat3" => WebGLDataType::Mat3,
+        "Mat4" => WebGLDataType::Mat4,
+        _ => return None,
+    };
+    Some(kind)
+}
+
+struct BlockField<'a> {
+    ident: &'a syn::Ident,
+    vis: &'a syn::Visibility,
+    element_type: &'a Type,
+    array_length: Option<u32>,
+    /// `None` for fields that are other `UniformBlock` structs
+    data_type: Option<WebGLDataType>,
+}
+
+fn parse_field(field: &syn::Field) -> Result<BlockField<'_>> {
+    let ident = field
+        .ident
+        .as_ref()
+        .ok_or_else(|| Error::new_spanned(field, "UniformBlock fields must be named"))?;
+    let (element_type, array_length) = match &field.ty {
+        Type::Array(array) => {
+            let length = match &array.len {
+                Expr::Lit(expr) => match &expr.lit {
+                    Lit::Int(length) => length.base10_parse::<u32>()?,
+                    _ => return Err(Error::new_spanned(&array.len, "expected an array length")),
+                },
+                _ => {
+                    return Err(Error::new_spanned(
+                        &array.len,
+                        "UniformBlock array lengths must be integer literals",
+                    ))
+                }
+            };
+            (array.elem.as_ref(), Some(length))
+        }
+        ty => (ty, None),
+    };
+    let data_type = field_data_type(element_type);
+    let is_path = matches!(element_type, Type::Path(type_path) if type_path.qself.is_none());
+    if data_type.is_none() && !is_path {
+        return Err(Error::new_spanned(
+            element_type,
+            "UniformBlock fields must be f32, i32, u32, bool, glam vectors or matrices, \
+             structs deriving UniformBlock, or fixed size arrays of them",
+        ));
+    }
+    Ok(BlockField {
+        ident,
+        vis: &field.vis,
+        element_type,
+        array_length,
+        data_type,
+    })
+}
+
+// Offset in the std140 struct. It is computed by the macro until a field is another
+// `UniformBlock`, the size of its std140 struct is only known by the compiler so the following
+// offsets are const expressions.
+#[derive(Clone)]
+enum Offset {
+    Known(u32),
+    Expr(TokenStream),
+}
+
+impl Offset {
+    fn tokens(&self) -> TokenStream {
+        match self {
+            Offset::Known(offset) => {
+                let offset = *offset as usize;
+                quote! { #offset }
+            }
+            Offset::Expr(expr) => expr.clone(),
+        }
+    }
+
+    fn round_up(&self, align: u32) -> Offset {
+        match self {
+            Offset::Known(offset) => Offset::Known(offset.div_ceil(align) * align),
+            Offset::Expr(expr) => {
+                let align = align as usize;
+                Offset::Expr(quote! { ((#expr) + #align - 1) / #align * #align })
+            }
+        }
+    }
+
+    fn add(&self, size: &Offset) -> Offset {
+        match (self, size) {
+            (Offset::Known(offset), Offset::Known(size)) => Offset::Known(offset + size),
+            _ => {
+                let (offset, size) = (self.tokens(), size.tokens());
+                Offset::Expr(quote! { (#offset + #size) })
+            }
+        }
+    }
+
+    // Size of the padding from `self` to `next`, `None` if there is none
+    fn padding_to(&self, next: &Offset) -> Option<TokenStream> {
+        match (self, next) {
+            (Offset::Known(offset), Offset::Known(next)) if next == offset => None,
+            (Offset::Known(offset), Offset::Known(next)) => {
+                let pad_size = (next - offset) as usize;
+                Some(quote! { #pad_size })
+            }
+            _ => {
+                let (offset, next) = (self.tokens(), next.tokens());
+                Some(quote! { (#next) - (#offset) })
+            }
+        }
+    }
+}
+
+// Reads `#[uniform_block(crate = "path", name = "BlockName")]`
+fn parse_attributes(input: &DeriveInput) -> Result<(Path, String)> {
+    let mut krate: Path = syn::parse_quote!(::rust_webgl2);
+    let mut name = input.ident.to_string();
+    for attr in &input.attrs {
+        if !attr.path.is_ident("uniform_block") {
+            continue;
+        }
+        let list = match attr.parse_meta()? {
+            Meta::List(list) => list,
+            meta => return Err(Error::new_spanned(meta, "expected #[uniform_block(...)]")),
+        };
+        for nested in list.nested {
+            match nested {
+                NestedMeta::Meta(Meta::NameValue(name_value)) => {
+                    let value = match &name_value.lit {
+                        Lit::Str(value) => value,
+                        lit => return Err(Error::new_spanned(lit, "expected a string")),
+                    };
+                    if name_value.path.is_ident("crate") {
+                        krate = value.parse()?;
+                    } else if name_value.path.is_ident("name") {
+                        name = value.value();
+                    } else {
+                        return Err(Error::new_spanned(
+                            name_value.path,
+                            "unknown uniform_block attribute, expected `crate` or `name`",
+                        ));
+                    }
+                }
+                nested => {
+                    return Err(Error::new_spanned(
+                        nested,
+                        "expected `crate = \"...\"` or `name = \"...\"`",
+                    ))
+                }
+            }
+        }
+    }
+    Ok((krate, name))
+}
+
+pub fn derive_uniform_block(input: DeriveInput) -> Result<TokenStream> {
+    let (krate, block_name) = parse_attributes(&input)?;
+    if !input.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &input.generics,
+            "UniformBlock cannot be derived for generic structs",
+        ));
+    }
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(Error::new_spanned(
+                    &input.ident,
+                    "UniformBlock can only be derived for structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(Error::new_spanned(
+                &input.ident,
+                "UniformBlock can only be derived for structs",
+            ))
+        }
+    };
+    let fields = fields.iter().map(parse_field).collect::<Result<Vec<_>>>()?;
+
+    // The std140 struct only has 4 byte aligned fields and explicit padding, so it has no
+    // padding bytes of its own and can implement Pod
+    let mut std140_fields = Vec::new();
+    let mut std140_values = Vec::new();
+    let mut uniforms = Vec::new();
+    let mut nested_blocks = Vec::new();
+    let mut offset = Offset::Known(0);
+    for (index, field) in fields.iter().enumerate() {
+        let ident = field.ident;
+        let field_vis = field.vis;
+        let ty = field.element_type;
+        let name = ident.to_string();
+        let array_length = match field.array_length {
+            Some(length) => quote! { ::core::option::Option::Some(#length) },
+            None => quote! { ::core::option::Option::None },
+        };
+        let (member_offset, size) = match field.data_type {
+            Some(kind) => {
+                let block = ShaderUniformBlock {
+                    binding_number: 0,
+                    name: block_name.clone(),
+                    uniforms: vec![ShaderUniform {
+                        array_length: field.array_length,
+                        kind: UniformKind::Data(kind),
+                        name: name.clone(),
+                        when: None,
+                    }],
+                    when: None,
+                };
+                let layout = block
+                    .std140_layout(&[])
+                    .map_err(|err| Error::new_spanned(ty, err.to_string()))?;
+                let member = &layout.members[0];
+                match field.array_length {
+                    Some(length) => {
+                        let length = length as usize;
+                        std140_fields.push(quote! {
+                            #field_vis #ident: [<#ty as #krate::Std140Field>::ArrayElement; #length]
+                        });
+                        std140_values.push(quote! {
+                            #ident: ::core::array::from_fn(|i| #krate::Std140Field::to_array_element(&self.#ident[i]))
+                        });
+                    }
+                    None => {
+                        std140_fields.push(
+                            quote! { #field_vis #ident: <#ty as #krate::Std140Field>::Packed },
+                        );
+                        std140_values
+                            .push(quote! { #ident: #krate::Std140Field::to_packed(&self.#ident) });
+                    }
+                }
+                let variant = format_ident!("{}", format!("{:?}", kind));
+                uniforms.push(quote! {
+                    #krate::ShaderUniform {
+                        array_length: #array_length,
+                        kind: #krate::UniformKind::Data(#krate::WebGLDataType::#variant),
+                        name: ::std::string::String::from(#name),
+                        when: ::core::option::Option::None,
+                    }
+                });
+                (offset.round_up(member.align), Offset::Known(member.size))
+            }
+            // Structs are aligned like a vec4, the std140 struct of a block is already padded to a
+            // multiple of 16 bytes
+            None => {
+                let std140 = quote! { <#ty as #krate::UniformBlock>::Std140 };
+                let struct_size = quote! { ::core::mem::size_of::<#std140>() };
+                let size = match field.array_length {
+                    Some(length) => {
+                        let length = length as usize;
+                        std140_fields.push(quote! { #field_vis #ident: [#std140; #length] });
+                        std140_values.push(quote! {
+                            #ident: ::core::array::from_fn(|i| #krate::UniformBlock::to_std140(&self.#ident[i]))
+                        });
+                        Offset::Expr(quote! { (#struct_size * #length) })
+                    }
+                    None => {
+                        std140_fields.push(quote! { #field_vis #ident: #std140 });
+                        std140_values
+                            .push(quote! { #ident: #krate::UniformBlock::to_std140(&self.#ident) });
+                        Offset::Expr(struct_size)
+                    }
+                };
+                uniforms.push(quote! {
+                    #krate::ShaderUniform {
+                        array_length: #array_length,
+                        kind: #krate::UniformKind::Struct(::std::string::String::from(
+                            <#ty as #krate::UniformBlock>::NAME,
+                        )),
+                        name: ::std::string::String::from(#name),
+                        when: ::core::option::Option::None,
+                    }
+                });
+                nested_blocks.push(ty);
+                (offset.round_up(16), size)
+            }
+        };
+        if let Some(pad_size) = offset.padding_to(&member_offset) {
+            let pad = format_ident!("_pad{}", index);
+            // The padding goes before the field
+            let field = std140_fields.pop().unwrap();
+            std140_fields.push(quote! { #pad: [u8; #pad_size] });
+            std140_fields.push(field);
+            std140_values.push(quote! { #pad: [0; #pad_size] });
+        }
+        offset = member_offset.add(&size);
+    }
+    let size = offset.round_up(16);
+    if let Some(pad_size) = offset.padding_to(&size) {
+        std140_fields.push(quote! { _pad_end: [u8; #pad_size] });
+        std140_values.push(quote! { _pad_end: [0; #pad_size] });
+    }
+
+    let ident = &input.ident;
+    let vis = &input.vis;
+    let std140_ident = format_ident!("{}Std140", ident);
+    let size = size.tokens();
+    let structs = (!nested_blocks.is_empty()).then(|| {
+        quote! {
+            fn structs() -> ::std::vec::Vec<#krate::ShaderStruct> {
+                let mut structs = ::std::vec::Vec::new();
+                #(#krate::push_block_struct::<#nested_blocks>(&mut structs);)*
+                structs
+            }
+        }
+    });
+    let doc = format!(
+        "std140 representation of [`{}`], generated by `UniformBlock`",
+        ident
+    );
+
+    Ok(quote! {
+        #[doc = #doc]
+        #[repr(C)]
+        #[derive(Clone, Copy, Debug)]
+        #vis struct #std140_ident {
+            #(#std140_fields,)*
+        }
+
+        const _: () = assert!(
+            ::core::mem::size_of::<#std140_ident>() == #size,
+            "the std140 struct does not match the computed block layout"
+        );
+
+        unsafe impl #krate::bytemuck::Zeroable for #std140_ident {}
+        unsafe impl #krate::bytemuck::Pod for #std140_ident {}
+
+        impl #krate::UniformBlock for #ident {
+            type Std140 = #std140_ident;
+            const NAME: &'static str = #block_name;
+
+            fn to_std140(&self) -> Self::Std140 {
+                #std140_ident {
+                    #(#std140_values,)*
+                }
+            }
+
+            fn uniforms() -> ::std::vec::Vec<#krate::ShaderUniform> {
+                ::std::vec![#(#uniforms),*]
+            }
+
+            #structs
+        }
+    })
+}
@@ -0,0 +1,192 @@
+use std::mem::size_of;
+
+use glam::{BVec3, Mat3, Mat4, Vec2, Vec3};
+use webgl2_shader_definition::{Std140Writer, UniformBlock, UniformKind, WebGLDataType};
+use webgl2_shader_generation::UniformBlock;
+
+// Checks the derived std140 struct against the layout calculator and `Std140Writer` for the same
+// members, `write` sets every member of the block in the writer
+fn assert_matches_writer<B: UniformBlock>(
+    block: &B,
+    size: usize,
+    write: impl FnOnce(&mut Std140Writer),
+) {
+    let layout = B::uniform_block(0).std140_layout(&B::structs()).unwrap();
+    assert_eq!(layout.size as usize, size);
+    assert_eq!(size_of::<B::Std140>(), size);
+    let mut writer = Std140Writer::new(layout);
+    write(&mut writer);
+    assert_eq!(bytemuck::bytes_of(&block.to_std140()), writer.as_bytes());
+}
+
+#[derive(UniformBlock)]
+#[uniform_block(crate = "webgl2_shader_definition")]
+struct Sun {
+    direction: Vec3,
+    intensity: f32,
+    color: Vec3,
+}
+
+#[test]
+fn float_after_vec3_is_packed() {
+    let sun = Sun {
+        direction: Vec3::new(1.0, 2.0, 3.0),
+        intensity: 4.0,
+        color: Vec3::new(5.0, 6.0, 7.0),
+    };
+    assert_matches_writer(&sun, 32, |writer| {
+        writer.set("direction", &sun.direction).unwrap();
+        writer.set("intensity", &sun.intensity).unwrap();
+        writer.set("color", &sun.color).unwrap();
+    });
+    assert_eq!(
+        Sun::uniform_block(0)
+            .std140_layout(&[])
+            .unwrap()
+            .member("intensity")
+            .unwrap()
+            .offset,
+        12
+    );
+}
+
+#[derive(UniformBlock)]
+#[uniform_block(crate = "webgl2_shader_definition")]
+struct Blur {
+    count: u32,
+    weights: [f32; 3],
+    offsets: [Vec2; 2],
+    tail: f32,
+}
+
+#[test]
+fn array_elements_have_a_vec4_stride() {
+    let blur = Blur {
+        count: 3,
+        weights: [0.25, 0.5, 0.25],
+        offsets: [Vec2::new(1.0, 2.0), Vec2::new(3.0, 4.0)],
+        tail: 9.0,
+    };
+    assert_matches_writer(&blur, 112, |writer| {
+        writer.set("count", &blur.count).unwrap();
+        writer.set_array("weights", &blur.weights).unwrap();
+        writer.set_array("offsets", &blur.offsets).unwrap();
+        writer.set("tail", &blur.tail).unwrap();
+    });
+}
+
+#[derive(UniformBlock)]
+#[uniform_block(crate = "webgl2_shader_definition")]
+struct Transform {
+    normal: Mat3,
+    scale: f32,
+    model: Mat4,
+}
+
+#[test]
+fn mat3_columns_are_padded() {
+    let transform = Transform {
+        normal: Mat3::from_cols_array(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0]),
+        scale: 10.0,
+        model: Mat4::from_cols_array(&std::array::from_fn(|i| 11.0 + i as f32)),
+    };
+    assert_matches_writer(&transform, 128, |writer| {
+        writer.set("normal", &transform.normal).unwrap();
+        writer.set("scale", &transform.scale).unwrap();
+        writer.set("model", &transform.model).unwrap();
+    });
+}
+
+#[derive(UniformBlock)]
+#[uniform_block(crate = "webgl2_shader_definition")]
+struct Flags {
+    enabled: bool,
+    mask: BVec3,
+    visible: bool,
+    count: i32,
+}
+
+#[test]
+fn bools_are_stored_as_uints() {
+    let flags = Flags {
+        enabled: true,
+        mask: BVec3::new(true, false, true),
+        visible: true,
+        count: -2,
+    };
+    assert_matches_writer(&flags, 48, |writer| {
+        writer.set("enabled", &flags.enabled).unwrap();
+        writer.set("mask", &flags.mask).unwrap();
+        writer.set("visible", &flags.visible).unwrap();
+        writer.set("count", &flags.count).unwrap();
+    });
+    let std140 = flags.to_std140();
+    assert_eq!(
+        bytemuck::cast_slice::<u8, u32>(&bytemuck::bytes_of(&std140)[16..32]),
+        [1, 0, 1, 1]
+    );
+}
+
+#[derive(UniformBlock)]
+#[uniform_block(crate = "webgl2_shader_definition")]
+struct Light {
+    color: Vec3,
+    intensity: f32,
+    position: Vec3,
+}
+
+#[derive(UniformBlock)]
+#[uniform_block(crate = "webgl2_shader_definition", name = "LightBlock")]
+struct Lights {
+    ambient: f32,
+    main: Light,
+    count: u32,
+    lights: [Light; 2],
+    exposure: f32,
+}
+
+fn light(seed: f32) -> Light {
+    Light {
+        color: Vec3::splat(seed),
+        intensity: seed + 1.0,
+        position: Vec3::new(seed + 2.0, seed + 3.0, seed + 4.0),
+    }
+}
+
+fn write_light(writer: &mut Std140Writer, prefix: &str, light: &Light) {
+    let name = |member: &str| format!("{}.{}", prefix, member);
+    writer.set(&name("color"), &light.color).unwrap();
+    writer.set(&name("intensity"), &light.intensity).unwrap();
+    writer.set(&name("position"), &light.position).unwrap();
+}
+
+#[test]
+fn nested_blocks_are_aligned_structs() {
+    let lights = Lights {
+        ambient: 0.5,
+        main: light(1.0),
+        count: 2,
+        lights: [light(10.0), light(20.0)],
+        exposure: 1.5,
+    };
+    assert_matches_writer(&lights, 144, |writer| {
+        writer.set("ambient", &lights.ambient).unwrap();
+        write_light(writer, "main", &lights.main);
+        writer.set("count", &lights.count).unwrap();
+        write_light(writer, "lights[0]", &lights.lights[0]);
+        write_light(writer, "lights[1]", &lights.lights[1]);
+        writer.set("exposure", &lights.exposure).unwrap();
+    });
+
+    let uniforms = Lights::uniforms();
+    assert_eq!(Lights::NAME, "LightBlock");
+    assert_eq!(uniforms[3].kind, UniformKind::Struct("Light".into()));
+    assert_eq!(uniforms[3].array_length, Some(2));
+    let structs = Lights::structs();
+    assert_eq!(structs.len(), 1);
+    assert_eq!(structs[0].name, "Light");
+    let intensity = &structs[0].members[1];
+    assert_eq!(intensity.name, "intensity");
+    assert_eq!(intensity.kind, UniformKind::Data(WebGLDataType::Float));
+    assert_eq!(intensity.array_length, None);
+}
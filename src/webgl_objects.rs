@@ -212,8 +212,8 @@ impl Deref for GlUniformBuffer {
 }
 
 impl GlUniformBuffer {
-    /// The data layout for 'src_data' has the std140 requirements
-    /// I am still unsure on how to enforce them form this API
+    /// The data layout for 'src_data' has the std140 requirements, a `Std140Writer` built from
+    /// `ShaderUniformBlock::std140_layout` packs the values with the right offsets and padding
     pub fn with_data<T: bytemuck::Pod>(
         graphics: &Graphics,
         src_data: &[T],
@@ -235,6 +235,18 @@ impl GlUniformBuffer {
             Err(_) => Err(()),
         }
     }
+    /// Creates the buffer with the std140 representation of `block`
+    pub fn with_block<B: UniformBlock>(
+        graphics: &Graphics,
+        block: &B,
+        usage: BufferUsage,
+        binding_point_location: u32,
+    ) -> Result<Self, ()> {
+        Self::with_data(graphics, &[block.to_std140()], usage, binding_point_location)
+    }
+    pub fn buffer_block<B: UniformBlock>(&self, block: &B) {
+        self.gl_buffer.buffer_data(&[block.to_std140()]);
+    }
     pub fn with_capacity(
         graphics: &Graphics,
         capacity: u16,
@@ -300,7 +312,9 @@ impl GlShader {
     }
 }
 
-#[derive(Clone, Copy)]
+/// Array variants upload every element with the `uniform*fv` overloads starting at the location
+/// of the first element
+#[derive(Clone)]
 pub enum FloatUniform {
     Scalar(f32),
     Vec2(glam::Vec2),
@@ -310,6 +324,15 @@ pub enum FloatUniform {
     Mat2(glam::Mat2),
     Mat3(glam::Mat3),
     Mat4(glam::Mat4),
+
+    ScalarArray(Vec<f32>),
+    Vec2Array(Vec<glam::Vec2>),
+    Vec3Array(Vec<glam::Vec3>),
+    Vec4Array(Vec<glam::Vec4>),
+
+    Mat2Array(Vec<glam::Mat2>),
+    Mat3Array(Vec<glam::Mat3>),
+    Mat4Array(Vec<glam::Mat4>),
 }
 
 impl FloatUniform {
@@ -322,20 +345,32 @@ impl FloatUniform {
             FloatUniform::Mat2(value) => uniform_setter.set_uniform_mat2(location, value),
             FloatUniform::Mat3(value) => uniform_setter.set_uniform_mat3(location, value),
             FloatUniform::Mat4(value) => uniform_setter.set_uniform_mat4(location, value),
+            FloatUniform::ScalarArray(values) => {
+                uniform_setter.set_uniform_f32_array(location, values)
+            }
+            FloatUniform::Vec2Array(values) => {
+                uniform_setter.set_uniform_vec2_array(location, values)
+            }
+            FloatUniform::Vec3Array(values) => {
+                uniform_setter.set_uniform_vec3_array(location, values)
+            }
+            FloatUniform::Vec4Array(values) => {
+                uniform_setter.set_uniform_vec4_array(location, values)
+            }
+            FloatUniform::Mat2Array(values) => {
+                uniform_setter.set_uniform_mat2_array(location, values)
+            }
+            FloatUniform::Mat3Array(values) => {
+                uniform_setter.set_uniform_mat3_array(location, values)
+            }
+            FloatUniform::Mat4Array(values) => {
+                uniform_setter.set_uniform_mat4_array(location, values)
+            }
         }
     }
 
     pub fn equal_variant(lhs: &Self, rhs: &Self) -> bool {
-        match (lhs, rhs) {
-            (FloatUniform::Scalar(_), FloatUniform::Scalar(_)) => true,
-            (FloatUniform::Vec2(_), FloatUniform::Vec2(_)) => true,
-            (FloatUniform::Vec3(_), FloatUniform::Vec3(_)) => true,
-            (FloatUniform::Vec4(_), FloatUniform::Vec4(_)) => true,
-            (FloatUniform::Mat2(_), FloatUniform::Mat2(_)) => true,
-            (FloatUniform::Mat3(_), FloatUniform::Mat3(_)) => true,
-            (FloatUniform::Mat4(_), FloatUniform::Mat4(_)) => true,
-            _ => false,
-        }
+        std::mem::discriminant(lhs) == std::mem::discriminant(rhs)
     }
 }
 
@@ -345,12 +380,17 @@ impl From<FloatUniform> for GlUniform {
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone)]
 pub enum IntUniform {
     Scalar(i32),
     Vec2(glam::IVec2),
     Vec3(glam::IVec3),
     Vec4(glam::IVec4),
+
+    ScalarArray(Vec<i32>),
+    Vec2Array(Vec<glam::IVec2>),
+    Vec3Array(Vec<glam::IVec3>),
+    Vec4Array(Vec<glam::IVec4>),
 }
 
 impl IntUniform {
@@ -360,16 +400,22 @@ impl IntUniform {
             IntUniform::Vec2(value) => uniform_setter.set_uniform_ivec2(location, value),
             IntUniform::Vec3(value) => uniform_setter.set_uniform_ivec3(location, value),
             IntUniform::Vec4(value) => uniform_setter.set_uniform_ivec4(location, value),
+            IntUniform::ScalarArray(values) => {
+                uniform_setter.set_uniform_i32_array(location, values)
+            }
+            IntUniform::Vec2Array(values) => {
+                uniform_setter.set_uniform_ivec2_array(location, values)
+            }
+            IntUniform::Vec3Array(values) => {
+                uniform_setter.set_uniform_ivec3_array(location, values)
+            }
+            IntUniform::Vec4Array(values) => {
+                uniform_setter.set_uniform_ivec4_array(location, values)
+            }
         }
     }
     pub fn equal_variant(lhs: &Self, rhs: &Self) -> bool {
-        match (lhs, rhs) {
-            (IntUniform::Scalar(_), IntUniform::Scalar(_)) => true,
-            (IntUniform::Vec2(_), IntUniform::Vec2(_)) => true,
-            (IntUniform::Vec3(_), IntUniform::Vec3(_)) => true,
-            (IntUniform::Vec4(_), IntUniform::Vec4(_)) => true,
-            _ => false,
-        }
+        std::mem::discriminant(lhs) == std::mem::discriminant(rhs)
     }
 }
 
@@ -379,12 +425,17 @@ impl From<IntUniform> for GlUniform {
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone)]
 pub enum UIntUniform {
     Scalar(u32),
     Vec2(glam::UVec2),
     Vec3(glam::UVec3),
     Vec4(glam::UVec4),
+
+    ScalarArray(Vec<u32>),
+    Vec2Array(Vec<glam::UVec2>),
+    Vec3Array(Vec<glam::UVec3>),
+    Vec4Array(Vec<glam::UVec4>),
 }
 
 impl UIntUniform {
@@ -394,16 +445,22 @@ impl UIntUniform {
             UIntUniform::Vec2(value) => uniform_setter.set_uniform_uvec2(location, value),
             UIntUniform::Vec3(value) => uniform_setter.set_uniform_uvec3(location, value),
             UIntUniform::Vec4(value) => uniform_setter.set_uniform_uvec4(location, value),
+            UIntUniform::ScalarArray(values) => {
+                uniform_setter.set_uniform_u32_array(location, values)
+            }
+            UIntUniform::Vec2Array(values) => {
+                uniform_setter.set_uniform_uvec2_array(location, values)
+            }
+            UIntUniform::Vec3Array(values) => {
+                uniform_setter.set_uniform_uvec3_array(location, values)
+            }
+            UIntUniform::Vec4Array(values) => {
+                uniform_setter.set_uniform_uvec4_array(location, values)
+            }
         }
     }
     pub fn equal_variant(lhs: &Self, rhs: &Self) -> bool {
-        match (lhs, rhs) {
-            (UIntUniform::Scalar(_), UIntUniform::Scalar(_)) => true,
-            (UIntUniform::Vec2(_), UIntUniform::Vec2(_)) => true,
-            (UIntUniform::Vec3(_), UIntUniform::Vec3(_)) => true,
-            (UIntUniform::Vec4(_), UIntUniform::Vec4(_)) => true,
-            _ => false,
-        }
+        std::mem::discriminant(lhs) == std::mem::discriminant(rhs)
     }
 }
 
@@ -413,11 +470,88 @@ impl From<UIntUniform> for GlUniform {
     }
 }
 
-#[derive(Clone, Copy)]
+/// Bool uniforms have no upload function of their own, they are uploaded as ints (0 or 1)
+#[derive(Clone)]
+pub enum BoolUniform {
+    Scalar(bool),
+    Vec2(glam::BVec2),
+    Vec3(glam::BVec3),
+    Vec4(glam::BVec4),
+
+    ScalarArray(Vec<bool>),
+    Vec2Array(Vec<glam::BVec2>),
+    Vec3Array(Vec<glam::BVec3>),
+    Vec4Array(Vec<glam::BVec4>),
+}
+
+impl BoolUniform {
+    pub fn set_uniform(&self, uniform_setter: &UniformSetter, location: &WebGlUniformLocation) {
+        match self {
+            BoolUniform::Scalar(value) => uniform_setter.set_uniform_i32(location, *value as i32),
+            BoolUniform::Vec2(value) => {
+                uniform_setter.set_uniform_ivec2(location, &bvec2_to_ivec2(*value))
+            }
+            BoolUniform::Vec3(value) => {
+                uniform_setter.set_uniform_ivec3(location, &bvec3_to_ivec3(*value))
+            }
+            BoolUniform::Vec4(value) => {
+                uniform_setter.set_uniform_ivec4(location, &bvec4_to_ivec4(*value))
+            }
+            BoolUniform::ScalarArray(values) => {
+                let values: Vec<i32> = values.iter().map(|value| *value as i32).collect();
+                uniform_setter.set_uniform_i32_array(location, &values)
+            }
+            BoolUniform::Vec2Array(values) => {
+                let values: Vec<IVec2> =
+                    values.iter().map(|value| bvec2_to_ivec2(*value)).collect();
+                uniform_setter.set_uniform_ivec2_array(location, &values)
+            }
+            BoolUniform::Vec3Array(values) => {
+                let values: Vec<IVec3> =
+                    values.iter().map(|value| bvec3_to_ivec3(*value)).collect();
+                uniform_setter.set_uniform_ivec3_array(location, &values)
+            }
+            BoolUniform::Vec4Array(values) => {
+                let values: Vec<IVec4> =
+                    values.iter().map(|value| bvec4_to_ivec4(*value)).collect();
+                uniform_setter.set_uniform_ivec4_array(location, &values)
+            }
+        }
+    }
+    pub fn equal_variant(lhs: &Self, rhs: &Self) -> bool {
+        std::mem::discriminant(lhs) == std::mem::discriminant(rhs)
+    }
+}
+
+fn bvec2_to_ivec2(value: BVec2) -> IVec2 {
+    IVec2::new(value.x as i32, value.y as i32)
+}
+
+fn bvec3_to_ivec3(value: BVec3) -> IVec3 {
+    IVec3::new(value.x as i32, value.y as i32, value.z as i32)
+}
+
+fn bvec4_to_ivec4(value: BVec4) -> IVec4 {
+    IVec4::new(
+        value.x as i32,
+        value.y as i32,
+        value.z as i32,
+        value.w as i32,
+    )
+}
+
+impl From<BoolUniform> for GlUniform {
+    fn from(uni: BoolUniform) -> Self {
+        GlUniform::Bool(uni)
+    }
+}
+
+#[derive(Clone)]
 pub enum GlUniform {
     Float(FloatUniform),
     Int(IntUniform),
     UInt(UIntUniform),
+    Bool(BoolUniform),
 }
 
 impl GlUniform {
@@ -426,6 +560,7 @@ impl GlUniform {
             GlUniform::Float(value) => value.set_uniform(uniform_setter, location),
             GlUniform::Int(value) => value.set_uniform(uniform_setter, location),
             GlUniform::UInt(value) => value.set_uniform(uniform_setter, location),
+            GlUniform::Bool(value) => value.set_uniform(uniform_setter, location),
         }
     }
     pub fn equal_variant(&self, other: &Self) -> bool {
@@ -433,6 +568,7 @@ impl GlUniform {
             (GlUniform::Float(lhs), GlUniform::Float(rhs)) => FloatUniform::equal_variant(lhs, rhs),
             (GlUniform::Int(lhs), GlUniform::Int(rhs)) => IntUniform::equal_variant(lhs, rhs),
             (GlUniform::UInt(lhs), GlUniform::UInt(rhs)) => UIntUniform::equal_variant(lhs, rhs),
+            (GlUniform::Bool(lhs), GlUniform::Bool(rhs)) => BoolUniform::equal_variant(lhs, rhs),
             (_, _) => {
                 return false;
             }